@@ -0,0 +1,103 @@
+//! Runs every `examples/*.b` with both dispatch strategies and reports the
+//! speedup of threaded dispatch over matching on each instruction.
+//!
+//! `BENCH_RUNS` sets how many runs the best time is taken from (default 3),
+//! `BENCH_MAX_STEPS` stops programs that don't terminate (default 500000000).
+
+use std::time::{Duration, Instant};
+
+use brainfuck_compiler::{
+    interpreter::{Dispatch, Error, Instruction, Interpreter},
+    lexer::Lexer,
+    optimizer::{Optimizer, OptimizerOptions},
+    parser::Parser,
+};
+
+fn env_or(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Input that makes interactive examples finish
+fn input(name: &str) -> &'static [u8] {
+    match name {
+        "factor.b" => b"1234567891011\n",
+        "life.b" => b"ab\nbc\nca\ncb\ncc\n\n\n\n\n\nq\n",
+        _ => b"",
+    }
+}
+
+/// Best wall time of `runs` runs, with the output and steps of the last one
+fn bench(
+    program: &[Instruction],
+    input: &[u8],
+    dispatch: Dispatch,
+    runs: u64,
+    max_steps: u64,
+) -> (Duration, Vec<u8>, u64) {
+    let mut best = Duration::MAX;
+    let mut output = Vec::new();
+    let mut steps = 0;
+
+    for _ in 0..runs {
+        output.clear();
+        let mut interpreter = Interpreter::builder()
+            .input(input)
+            .output(&mut output)
+            .max_steps(max_steps)
+            .dispatch(dispatch)
+            .build_with_instructions(program.to_vec());
+
+        let start = Instant::now();
+        match interpreter.run() {
+            Ok(()) | Err(Error::StepLimitExceeded) => {}
+            Err(err) => panic!("{}", err),
+        }
+        best = best.min(start.elapsed());
+        steps = interpreter.steps();
+    }
+    (best, output, steps)
+}
+
+fn main() {
+    let runs = env_or("BENCH_RUNS", 3).max(1);
+    let max_steps = env_or("BENCH_MAX_STEPS", 500_000_000);
+
+    let mut paths: Vec<_> = std::fs::read_dir("examples")
+        .expect("run from the repository root")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "b"))
+        .collect();
+    paths.sort();
+
+    println!(
+        "{:<20} {:>12} {:>10} {:>10} {:>8}",
+        "example", "steps", "match", "threaded", "speedup"
+    );
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = std::fs::read_to_string(&path).unwrap();
+
+        let ast = Parser::new(Lexer::new(source)).parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        let options = OptimizerOptions::default();
+        optimizer.optimize_with(&options);
+        let program = optimizer.compile(&options);
+
+        let input = input(&name);
+        let (matched, expected, steps) = bench(&program, input, Dispatch::Match, runs, max_steps);
+        let (threaded, output, _) = bench(&program, input, Dispatch::Threaded, runs, max_steps);
+        assert!(output == expected, "{}: dispatch strategies disagree", name);
+
+        println!(
+            "{:<20} {:>12} {:>8.1}ms {:>8.1}ms {:>7.2}x",
+            name,
+            steps,
+            matched.as_secs_f64() * 1000.0,
+            threaded.as_secs_f64() * 1000.0,
+            matched.as_secs_f64() / threaded.as_secs_f64(),
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::interpreter::Interpreter;
+use crate::ir::{self, Node};
+use crate::lexer::Span;
+use crate::profiler::loop_source;
+
+/// What one iteration of a loop does. Cells are relative to the pointer at
+/// the start of the iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopEffects {
+    /// Net pointer movement, `None` when it depends on the tape
+    pub shift: Option<isize>,
+    pub reads: BTreeSet<isize>,
+    pub writes: BTreeSet<isize>,
+    /// Whether cells outside `reads` and `writes` may be accessed too, which
+    /// happens after a scan or an unbalanced nested loop
+    pub unknown_cells: bool,
+    pub io: bool,
+    /// Change of the loop cell, `None` unless the loop is balanced and only
+    /// ever adds a fixed amount to it
+    pub delta: Option<i8>,
+}
+
+impl LoopEffects {
+    /// Whether every iteration ends on the cell it started on
+    pub fn is_balanced(&self) -> bool {
+        self.shift == Some(0)
+    }
+}
+
+/// A loop of a program, in the order their `[` appear
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopAnalysis {
+    /// Index of the loop's `BeginLoop` in `Interpreter::compile_ir`'s output
+    pub start: usize,
+    /// Index of the loop's `EndLoop`
+    pub end: usize,
+    pub depth: usize,
+    pub effects: LoopEffects,
+}
+
+pub fn loop_effects(body: &[Node]) -> LoopEffects {
+    let mut effects = LoopEffects {
+        shift: None,
+        reads: BTreeSet::from([0]),
+        writes: BTreeSet::new(),
+        unknown_cells: false,
+        io: false,
+        delta: Some(0),
+    };
+
+    let mut position = Some(0);
+    for node in body {
+        effects.step(node, &mut position);
+    }
+
+    // the loop condition is checked wherever the iteration ends
+    effects.read(position);
+    effects.shift = position;
+    if position != Some(0) {
+        effects.delta = None;
+    }
+    effects
+}
+
+impl LoopEffects {
+    fn read(&mut self, position: Option<isize>) {
+        match position {
+            Some(position) => {
+                self.reads.insert(position);
+            }
+            None => self.unknown_cells = true,
+        }
+    }
+
+    /// Records a write, `add` is the amount added to the cell if that's all
+    /// the write did
+    fn write(&mut self, position: Option<isize>, add: Option<i8>) {
+        match position {
+            Some(position) => {
+                self.writes.insert(position);
+            }
+            None => self.unknown_cells = true,
+        }
+        match (position, add) {
+            (Some(0), Some(add)) => self.delta = self.delta.map(|delta| delta.wrapping_add(add)),
+            (Some(0) | None, _) => self.delta = None,
+            _ => {}
+        }
+    }
+
+    fn step(&mut self, node: &Node, position: &mut Option<isize>) {
+        let cell = position.map(|p| p + node.offset());
+        match node {
+            Node::Add { delta, .. } => {
+                self.read(cell);
+                self.write(cell, Some(*delta));
+            }
+            Node::Move(n) => *position = position.map(|p| p + n),
+            Node::Set { .. } => self.write(cell, None),
+            Node::Input { .. } => {
+                self.io = true;
+                self.write(cell, None);
+            }
+            Node::Output { .. } => {
+                self.io = true;
+                self.read(cell);
+            }
+            Node::OutputBytes(_) => self.io = true,
+            Node::Scan(_) => {
+                self.read(*position);
+                self.unknown_cells = true;
+                *position = None;
+            }
+            Node::Loop(ir::Loop { body, .. }) | Node::If(body) => {
+                let inner = loop_effects(body);
+                self.io |= inner.io;
+                self.unknown_cells |= inner.unknown_cells;
+
+                let Some(start) = *position else {
+                    self.unknown_cells = true;
+                    self.delta = None;
+                    return;
+                };
+                self.reads
+                    .extend(inner.reads.iter().map(|cell| start + cell));
+                self.writes
+                    .extend(inner.writes.iter().map(|cell| start + cell));
+                // may not run at all, so any change to the loop cell isn't a
+                // fixed amount
+                if inner.unknown_cells || inner.writes.contains(&-start) {
+                    self.delta = None;
+                }
+                if !inner.is_balanced() {
+                    self.unknown_cells = true;
+                    *position = None;
+                }
+            }
+        }
+    }
+}
+
+/// Instructions `Interpreter::compile_ir` makes of a node other than a loop
+/// or an `If`, which moves to a cell away from the pointer and back
+fn instructions(node: &Node) -> usize {
+    if node.offset() == 0 {
+        1
+    } else {
+        3
+    }
+}
+
+/// Effects of every loop in `nodes`, nested ones included
+pub fn analyze(nodes: &[Node]) -> Vec<LoopAnalysis> {
+    fn walk(nodes: &[Node], depth: usize, index: &mut usize, loops: &mut Vec<LoopAnalysis>) {
+        for node in nodes {
+            match node {
+                Node::Loop(inner) => {
+                    let current = loops.len();
+                    loops.push(LoopAnalysis {
+                        start: *index,
+                        end: *index,
+                        depth,
+                        effects: loop_effects(&inner.body),
+                    });
+                    *index += 1;
+                    walk(&inner.body, depth + 1, index, loops);
+                    loops[current].end = *index;
+                    *index += 1;
+                }
+                // not a loop, but loops in its body are
+                Node::If(body) => {
+                    *index += 1;
+                    walk(body, depth, index, loops);
+                }
+                node => *index += instructions(node),
+            }
+        }
+    }
+
+    let mut loops = Vec::new();
+    walk(nodes, 0, &mut 0, &mut loops);
+    loops
+}
+
+/// Cells the pointer can reach, relative to the cell a program starts on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TapeBounds {
+    /// Lowest cell, `None` when a loop can move the pointer left without limit
+    pub min: Option<isize>,
+    /// Highest cell, `None` when a loop can move the pointer right without limit
+    pub max: Option<isize>,
+    /// Index in `Interpreter::compile_ir`'s output of the first move that always
+    /// takes the pointer left of cell 0, if the program gets that far
+    pub left_of_start: Option<usize>,
+}
+
+impl TapeBounds {
+    /// Cells a tape needs so the program can never move past its end
+    pub fn tape_size(&self) -> Option<usize> {
+        self.max.map(|max| max.max(0) as usize + 1)
+    }
+
+    /// Whether every cell the pointer can reach is on a tape of `tape_size`
+    /// cells, so moves don't need bounds checks
+    pub fn fits(&self, tape_size: usize) -> bool {
+        matches!(
+            (self.min, self.max),
+            (Some(min), Some(max)) if min >= 0 && (max as usize) < tape_size
+        )
+    }
+}
+
+impl fmt::Display for TapeBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "tape: cells {}..={}", min, max),
+            (Some(min), None) => write!(f, "tape: cells {}.., unbounded to the right", min),
+            (None, Some(max)) => write!(f, "tape: cells ..={}, unbounded to the left", max),
+            (None, None) => write!(f, "tape: unbounded"),
+        }
+    }
+}
+
+/// Where the pointer may be while walking a program, and where it may have
+/// been. `None` stands for no limit in that direction.
+#[derive(Debug, Clone, Copy)]
+struct Reach {
+    low: Option<isize>,
+    high: Option<isize>,
+    min: Option<isize>,
+    max: Option<isize>,
+}
+
+fn offset(a: Option<isize>, b: Option<isize>) -> Option<isize> {
+    a.zip(b).map(|(a, b)| a + b)
+}
+
+impl Reach {
+    fn start() -> Self {
+        Reach {
+            low: Some(0),
+            high: Some(0),
+            min: Some(0),
+            max: Some(0),
+        }
+    }
+
+    /// Records that the pointer reached every cell from `low` to `high`
+    fn visit(&mut self, low: Option<isize>, high: Option<isize>) {
+        self.min = self.min.zip(low).map(|(a, b)| a.min(b));
+        self.max = self.max.zip(high).map(|(a, b)| a.max(b));
+    }
+
+    fn step(&mut self, n: isize) {
+        self.low = self.low.map(|low| low + n);
+        self.high = self.high.map(|high| high + n);
+        self.visit(self.low, self.high);
+    }
+
+    /// Walks `nodes`, counting instructions in `index`. Only moves at the top
+    /// level always run, so only those set `left_of_start`.
+    fn walk(
+        &mut self,
+        nodes: &[Node],
+        top_level: bool,
+        index: &mut usize,
+        left_of_start: &mut Option<usize>,
+    ) {
+        for node in nodes {
+            let high = match node {
+                Node::Move(n) => {
+                    self.step(*n);
+                    self.high
+                }
+                Node::Scan(step) if *step < 0 => {
+                    self.low = None;
+                    self.visit(None, self.high);
+                    self.high
+                }
+                Node::Scan(_) => {
+                    self.high = None;
+                    self.visit(self.low, None);
+                    self.high
+                }
+                Node::Loop(ir::Loop { body, .. }) | Node::If(body) => {
+                    let looping = matches!(node, Node::Loop(_));
+                    let mut inner = Reach::start();
+                    *index += 1;
+                    inner.walk(body, false, index, left_of_start);
+                    if looping {
+                        *index += 1;
+                    }
+                    self.repeat(&inner, looping);
+                    continue;
+                }
+                // the pointer moves to a cell away from it for the node
+                node => {
+                    let cell = Some(node.offset());
+                    let high = offset(self.high, cell);
+                    self.visit(offset(self.low, cell), high);
+                    high
+                }
+            };
+
+            let left = matches!(high, Some(high) if high < 0);
+            if top_level && left && left_of_start.is_none() {
+                *left_of_start = Some(*index);
+            }
+            *index += instructions(node);
+        }
+    }
+
+    /// Applies a body that was walked from `Reach::start`, run any number of
+    /// times for a loop and at most once otherwise
+    fn repeat(&mut self, body: &Reach, looping: bool) {
+        let balanced = body.low == Some(0) && body.high == Some(0);
+        if balanced || !looping {
+            self.visit(offset(self.low, body.min), offset(self.high, body.max));
+            if !balanced {
+                self.low = self
+                    .low
+                    .zip(offset(self.low, body.low))
+                    .map(|(a, b)| a.min(b));
+                self.high = self
+                    .high
+                    .zip(offset(self.high, body.high))
+                    .map(|(a, b)| a.max(b));
+            }
+        } else if matches!(body.low, Some(low) if low >= 0) {
+            // no iteration starts left of where the loop did
+            self.visit(offset(self.low, body.min), None);
+            self.high = None;
+        } else if matches!(body.high, Some(high) if high <= 0) {
+            self.visit(None, offset(self.high, body.max));
+            self.low = None;
+        } else {
+            self.low = None;
+            self.high = None;
+            self.visit(None, None);
+        }
+    }
+}
+
+/// Bounds of the cells `nodes` can move the pointer to. Loops that don't end
+/// where they started make the tape unbounded in the direction they move in.
+pub fn tape_bounds(nodes: &[Node]) -> TapeBounds {
+    let mut reach = Reach::start();
+    let mut left_of_start = None;
+    reach.walk(nodes, true, &mut 0, &mut left_of_start);
+    TapeBounds {
+        min: reach.min,
+        max: reach.max,
+        left_of_start,
+    }
+}
+
+/// Prints every loop with its effects, at its source position when `spans`
+/// (from `debugger::source_map`) are given
+pub fn report(nodes: &[Node], spans: Option<&[Span]>, out: &mut dyn Write) -> io::Result<()> {
+    let program = Interpreter::compile_ir(nodes);
+    let loops = analyze(nodes);
+
+    let balanced = loops.iter().filter(|l| l.effects.is_balanced()).count();
+    writeln!(out, "{} loops, {} balanced", loops.len(), balanced)?;
+
+    let bounds = tape_bounds(nodes);
+    writeln!(out, "{}", bounds)?;
+    if let Some(index) = bounds.left_of_start {
+        writeln!(out, "moves left of cell 0 at {}", location(spans, index))?;
+    }
+
+    for analysis in &loops {
+        let effects = &analysis.effects;
+        let position = location(spans, analysis.start);
+        let indent = "  ".repeat(analysis.depth);
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{}{} {}",
+            indent,
+            position,
+            loop_source(&program[analysis.start..=analysis.end])
+        )?;
+        writeln!(
+            out,
+            "{}  {}, delta {}{}",
+            indent,
+            match effects.shift {
+                Some(0) => String::from("balanced"),
+                Some(shift) => format!("shift {:+}", shift),
+                None => String::from("shift ?"),
+            },
+            effects
+                .delta
+                .map_or("?".to_string(), |delta| format!("{:+}", delta)),
+            if effects.io { ", does I/O" } else { "" },
+        )?;
+        writeln!(
+            out,
+            "{}  reads {}, writes {}",
+            indent,
+            cells(&effects.reads, effects.unknown_cells),
+            cells(&effects.writes, effects.unknown_cells),
+        )?;
+    }
+    Ok(())
+}
+
+/// Source position of an instruction, or its index without `spans`
+fn location(spans: Option<&[Span]>, index: usize) -> String {
+    match spans.and_then(|spans| spans.get(index)) {
+        Some(span) => format!("{}:{}", span.line, span.column),
+        None => format!("@{}", index),
+    }
+}
+
+fn cells(cells: &BTreeSet<isize>, unknown: bool) -> String {
+    let mut list: Vec<String> = cells.iter().map(ToString::to_string).collect();
+    if unknown {
+        list.push(String::from("?"));
+    }
+    format!("[{}]", list.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<Node> {
+        ir::lower(
+            &Parser::new(Lexer::new(String::from(source)))
+                .parse_helper()
+                .data,
+        )
+    }
+
+    fn effects(source: &str) -> LoopEffects {
+        match &parse(source)[..] {
+            [Node::Loop(inner)] => loop_effects(&inner.body),
+            _ => panic!("expected a single loop"),
+        }
+    }
+
+    #[test]
+    fn test_balanced() {
+        let effects = effects("[->>+++<<]");
+
+        assert!(effects.is_balanced());
+        assert_eq!(effects.reads, BTreeSet::from([0, 2]));
+        assert_eq!(effects.writes, BTreeSet::from([0, 2]));
+        assert_eq!(effects.delta, Some(-1));
+        assert!(!effects.unknown_cells);
+        assert!(!effects.io);
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        let effects = effects("[.>]");
+
+        assert_eq!(effects.shift, Some(1));
+        assert_eq!(effects.reads, BTreeSet::from([0, 1]));
+        assert_eq!(effects.delta, None);
+        assert!(effects.io);
+    }
+
+    #[test]
+    fn test_nested() {
+        // the inner loop clears cell 1, which leaves the loop cell alone
+        let effects = effects("[->[-]<]");
+        assert_eq!(effects.delta, Some(-1));
+        assert_eq!(effects.writes, BTreeSet::from([0, 1]));
+
+        // an inner loop on the loop cell runs an unknown number of times
+        assert_eq!(self::effects("[[-]+]").delta, None);
+
+        let effects = self::effects("[>[<]+]");
+        assert_eq!(effects.shift, None);
+        assert!(effects.unknown_cells);
+        assert_eq!(effects.delta, None);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let loops = analyze(&parse("+[>[-]<-] comment [,]"));
+
+        let positions: Vec<_> = loops.iter().map(|l| (l.start, l.end, l.depth)).collect();
+        assert_eq!(positions, [(1, 8, 0), (3, 5, 1), (9, 11, 0)]);
+
+        let mut out = Vec::new();
+        report(&parse("[->+<]"), None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 loops, 1 balanced\ntape: cells 0..=1\n\n@0 [->+<]\n  balanced, delta -1\n  reads [0, 1], writes [0, 1]\n"
+        );
+    }
+
+    #[test]
+    fn test_tape_bounds() {
+        let bounds = tape_bounds(&parse(">>+<+[>+<-]>"));
+        assert_eq!((bounds.min, bounds.max), (Some(0), Some(2)));
+        assert_eq!(bounds.tape_size(), Some(3));
+        assert!(bounds.fits(3));
+        assert!(!bounds.fits(2));
+
+        // a loop moving right can go on forever, but never left of its start
+        let bounds = tape_bounds(&parse("+[>]<"));
+        assert_eq!((bounds.min, bounds.max), (Some(-1), None));
+        assert_eq!(bounds.tape_size(), None);
+        assert_eq!(bounds.left_of_start, None);
+
+        let bounds = tape_bounds(&parse(">>+[<]"));
+        assert_eq!((bounds.min, bounds.max), (None, Some(2)));
+
+        assert_eq!(
+            tape_bounds(&parse(",[>[<]]")).to_string(),
+            "tape: unbounded"
+        );
+    }
+
+    #[test]
+    fn test_left_of_start() {
+        let bounds = tape_bounds(&parse("+>[-]<<+"));
+        assert_eq!(bounds.left_of_start, Some(5));
+        assert_eq!(bounds.min, Some(-1));
+        assert!(!bounds.fits(30000));
+
+        // the loop may not run
+        assert_eq!(tape_bounds(&parse(",[<]")).left_of_start, None);
+
+        let mut out = Vec::new();
+        report(&parse("<"), None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0 loops, 0 balanced\ntape: cells -1..=0\nmoves left of cell 0 at @0\n"
+        );
+    }
+}
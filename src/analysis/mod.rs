@@ -0,0 +1,5 @@
+mod analysis;
+
+pub use self::analysis::{
+    analyze, loop_effects, report, tape_bounds, LoopAnalysis, LoopEffects, TapeBounds,
+};
@@ -1,17 +1,182 @@
+use std::ffi::OsString;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+use crate::emit::{EmitFormat, Stage};
+use crate::interpreter::{FlushPolicy, DEFAULT_JOURNAL_LIMIT};
+use crate::profiler::TraceFormat;
 
 #[derive(Parser, Clone)]
 #[command(version, about, long_about = None)]
 #[command(next_line_help = true)]
+#[command(after_help = "`fucker <input file>` is short for `fucker run <input file>`")]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Run a program, the default when no command is given
+    Run(RunArgs),
+    /// Step through a program interactively
+    Debug(DebugArgs),
+    /// Animate a program's source, tape and output in the terminal
+    Viz(VizArgs),
+    /// Print how every loop moves the pointer and which cells it touches
+    Analyze(AnalyzeArgs),
+}
+
+#[derive(Args, Clone)]
+pub struct RunArgs {
     #[arg(value_name = "input file")]
     pub file: PathBuf,
 
     #[arg(short, long, action = clap::ArgAction::SetTrue, default_value = "false")]
     pub debug: bool,
 
+    #[arg(long, value_enum, default_value_t = FlushPolicy::Exit, help="When to flush program output")]
+    pub flush: FlushPolicy,
+
+    #[arg(long, value_name = "N", help = "Stop after executing N instructions of the optimized program")]
+    pub max_steps: Option<u64>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, help="Stop after running for DURATION (e.g. 500ms, 10s, 2m)")]
+    pub timeout: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "CELLS",
+        help = "Number of cells on the tape [default: 30000, or fewer when the program provably can't reach that far]"
+    )]
+    pub tape_size: Option<usize>,
+
+    #[arg(long, value_name = "RANGE", num_args = 0..=1, require_equals = true, value_parser = parse_range, help = "Print the tape as hex and ASCII on exit, e.g. --dump-tape=0..64 [default: up to the last used cell]")]
+    pub dump_tape: Option<Option<Range<usize>>>,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help = "Report how often each cell was read and written, and the furthest pointer position")]
+    pub tape_heatmap: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help = "Run the unoptimized program, count executed instructions and report the hottest source loops on stderr")]
+    pub profile: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        help = "Number of loops in the --profile report"
+    )]
+    pub profile_top: usize,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write an execution trace with loops as stack frames to FILE"
+    )]
+    pub trace: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = TraceFormat::Chrome, help = "Format of the --trace file")]
+    pub trace_format: TraceFormat,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1000,
+        help = "Leave loop runs shorter than N instructions out of chrome traces"
+    )]
+    pub trace_min_steps: u64,
+
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true, default_missing_value = "lcov.info", help = "Run the unoptimized program, print which commands ran and write an lcov report to FILE [default: lcov.info]")]
+    pub coverage: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the interpreter state to FILE when the program stops, including on Ctrl-C"
+    )]
+    pub save_state_on_exit: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Continue from a state written by --save-state-on-exit"
+    )]
+    pub resume: Option<PathBuf>,
+
+    #[arg(
+        long,
+        action = clap::ArgAction::SetTrue,
+        help = "Read all of stdin, run the program with and without optimizations and fail with the first pass that changes its output, tape or pointer"
+    )]
+    pub verify_optimizations: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STAGE",
+        help = "Print what a stage of the pipeline produces instead of running the program"
+    )]
+    pub emit: Option<Stage>,
+
+    #[arg(long, value_enum, default_value_t = EmitFormat::Text, help = "Format of --emit")]
+    pub emit_format: EmitFormat,
+
+    #[command(flatten)]
+    pub optimizations: OptimizationArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct DebugArgs {
+    #[arg(value_name = "input file")]
+    pub file: PathBuf,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Step through the optimized instructions instead of the source")]
+    pub optimized: bool,
+
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_JOURNAL_LIMIT, help = "Number of executed instructions kept for reverse-step and reverse-continue, 0 turns recording off")]
+    pub history: usize,
+
+    #[command(flatten)]
+    pub optimizations: OptimizationArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct VizArgs {
+    #[arg(value_name = "input file")]
+    pub file: PathBuf,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Show the optimized instructions instead of the source")]
+    pub optimized: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 20,
+        help = "Instructions executed per second"
+    )]
+    pub speed: u64,
+
+    #[command(flatten)]
+    pub optimizations: OptimizationArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct AnalyzeArgs {
+    #[arg(value_name = "input file")]
+    pub file: PathBuf,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Analyze the optimized program instead of the source")]
+    pub optimized: bool,
+
+    #[command(flatten)]
+    pub optimizations: OptimizationArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct OptimizationArgs {
     #[arg(long, action = clap::ArgAction::SetTrue, help="Disable scan  optimization ([>], [<])")]
     pub f_no_optimize_scan: bool,
 
@@ -20,9 +185,244 @@ pub struct Cli {
 
     #[arg(long, action = clap::ArgAction::SetTrue, help="Disable loop  optimization ([[...]])")]
     pub f_no_optimize_loops: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Disable running loops that end on a cleared cell at most once ([...[-]])")]
+    pub f_no_optimize_ifs: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Disable writing output of known values at once ([-]+++.+.)")]
+    pub f_no_optimize_output: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Disable fusing neighbouring instructions (>], -], [-]>)")]
+    pub f_no_optimize_peephole: bool,
+
+    #[arg(long, action = clap::ArgAction::SetTrue, help="Disable running the program up to its first input while optimizing")]
+    pub f_no_optimize_partial_eval: bool,
+}
+
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let (value, unit) = match arg.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => arg.split_at(index),
+        None => (arg, "s"),
+    };
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", arg))?;
+
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => {
+            return Err(format!(
+                "unknown duration unit `{}`, use ms, s, m or h",
+                unit
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+/// Parses `a..b`, `a..` or `..b`
+fn parse_range(arg: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid range `{}`, use e.g. 0..64", arg);
+    let (start, end) = arg.split_once("..").ok_or_else(invalid)?;
+
+    let start = match start {
+        "" => 0,
+        start => start.parse().map_err(|_| invalid())?,
+    };
+    let end = match end {
+        "" => usize::MAX,
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    Ok(start..end)
+}
+
+/// `fucker <file>` is short for `fucker run <file>`
+fn with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let command = Cli::command();
+    let explicit = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        command.get_subcommands().any(|sub| sub.get_name() == arg)
+            || ["help", "-h", "--help", "-V", "--version"].contains(&arg)
+    });
+
+    if !explicit && args.len() > 1 {
+        args.insert(1, OsString::from("run"));
+    }
+    args
 }
 
 pub fn parse_args() -> Cli {
-    let cli = Cli::parse();
-    cli
+    Cli::parse_from(with_default_command(std::env::args_os().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(with_default_command(
+            args.iter().map(OsString::from).collect(),
+        ))
+    }
+
+    #[test]
+    fn test_default_command() {
+        let cli = parse(&["fucker", "hello.b", "--max-steps", "10"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                max_steps: Some(10),
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "hello.b", "--profile"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                profile: true,
+                profile_top: 10,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "hello.b", "--coverage"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Run(args) if args.coverage == Some(PathBuf::from("lcov.info")))
+        );
+
+        let cli = parse(&["fucker", "--coverage", "hello.b"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Run(args) if args.coverage == Some(PathBuf::from("lcov.info")))
+        );
+
+        let cli = parse(&["fucker", "--coverage=out.info", "hello.b"]).unwrap();
+        assert!(
+            matches!(cli.command, Command::Run(args) if args.coverage == Some(PathBuf::from("out.info")))
+        );
+
+        let cli = parse(&[
+            "fucker",
+            "hello.b",
+            "--trace",
+            "t.txt",
+            "--trace-format=collapsed",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                trace: Some(_),
+                trace_format: TraceFormat::Collapsed,
+                trace_min_steps: 1000,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "--verify-optimizations", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                verify_optimizations: true,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "--emit=optimized-ast", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                emit: Some(Stage::OptimizedIr),
+                emit_format: EmitFormat::Text,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                tape_size: None,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "--tape-size", "64", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs {
+                tape_size: Some(64),
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "-d", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Run(RunArgs { debug: true, .. })
+        ));
+
+        let cli = parse(&["fucker", "run", "hello.b", "--f-no-optimize-scan"]).unwrap();
+        assert!(matches!(cli.command, Command::Run(args) if args.optimizations.f_no_optimize_scan));
+
+        let cli = parse(&["fucker", "debug", "hello.b", "--optimized"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Debug(DebugArgs {
+                optimized: true,
+                history: DEFAULT_JOURNAL_LIMIT,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "viz", "hello.b", "--speed", "100"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Viz(VizArgs {
+                speed: 100,
+                optimized: false,
+                ..
+            })
+        ));
+
+        let cli = parse(&["fucker", "analyze", "hello.b"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Analyze(AnalyzeArgs {
+                optimized: false,
+                ..
+            })
+        ));
+
+        assert!(parse(&["fucker"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("16..32"), Ok(16..32));
+        assert_eq!(parse_range("..8"), Ok(0..8));
+        assert_eq!(parse_range("100.."), Ok(100..usize::MAX));
+        assert!(parse_range("8").is_err());
+        assert!(parse_range("a..b").is_err());
+
+        let cli = parse(&["fucker", "--dump-tape", "hello.b"]).unwrap();
+        assert!(matches!(cli.command, Command::Run(args) if args.dump_tape == Some(None)));
+
+        let cli = parse(&["fucker", "--dump-tape=0..64", "hello.b"]).unwrap();
+        assert!(matches!(cli.command, Command::Run(args) if args.dump_tape == Some(Some(0..64))));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("fast").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
 }
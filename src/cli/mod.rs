@@ -1,3 +1,5 @@
 mod cli;
 
-pub use cli::{parse_args, Cli};
+pub use cli::{
+    parse_args, AnalyzeArgs, Cli, Command, DebugArgs, OptimizationArgs, RunArgs, VizArgs,
+};
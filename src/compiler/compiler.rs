@@ -1,26 +1,346 @@
-#![allow(dead_code)]
+use std::io::{self, Write};
 
-use crate::parser::ASTNode;
+use crate::analysis::{tape_bounds, TapeBounds};
+use crate::ir::Node;
+use crate::optimizer::fold_offsets;
+
+/// Written to stderr before exiting with status 1 when the pointer leaves the
+/// tape, like `interpreter::Error::PointerOutOfBounds`
+const OUT_OF_BOUNDS: &[u8] = b"Memory pointer moved outside of the tape\n";
 
 pub struct Compiler {
-    nodes: Vec<ASTNode>,
+    program: Vec<Node>,
+    bounds: TapeBounds,
 }
 
 impl Compiler {
-    pub fn new(nodes: Vec<ASTNode>) -> Self {
-        Compiler { nodes }
+    /// Takes the IR `Optimizer` left in `program` and folds its moves into
+    /// cell offsets
+    pub fn new(nodes: Vec<Node>) -> Self {
+        Compiler {
+            bounds: tape_bounds(&nodes),
+            program: fold_offsets(nodes),
+        }
+    }
+
+    /// Whether moves need to check the pointer against the ends of a tape of
+    /// `tape_size` cells, which they don't when no move can leave it
+    pub fn needs_bounds_checks(&self, tape_size: usize) -> bool {
+        !self.bounds.fits(tape_size)
+    }
+
+    /// Writes the program as x86-64 Linux assembly for GNU `as`, with a tape
+    /// of `tape_size` cells and I/O through raw syscalls, so `as` and `ld`
+    /// make a static executable of it without libc. Every `OutputBytes` is a
+    /// `.rodata` string written by a single `write`. Bounds checks are left
+    /// out when `needs_bounds_checks` says they can't fail.
+    pub fn assembly(&self, tape_size: usize, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "    .intel_syntax noprefix")?;
+        writeln!(out, "    .globl _start")?;
+        writeln!(out)?;
+        writeln!(out, "    .text")?;
+        writeln!(out, "_start:")?;
+        // rbx points at the current cell, r12 and r13 at the ends of the tape
+        writeln!(out, "    lea rbx, [rip + tape]")?;
+        writeln!(out, "    lea r12, [rip + tape]")?;
+        writeln!(out, "    lea r13, [rip + tape + {}]", tape_size)?;
+
+        let mut codegen = Codegen {
+            out,
+            labels: 0,
+            strings: Vec::new(),
+            checks: self.needs_bounds_checks(tape_size),
+        };
+        codegen.nodes(&self.program)?;
+        let Codegen { out, strings, .. } = codegen;
+
+        writeln!(out, "    mov eax, 60")?;
+        writeln!(out, "    xor edi, edi")?;
+        writeln!(out, "    syscall")?;
+        writeln!(out, ".Lout_of_bounds:")?;
+        writeln!(out, "    mov eax, 1")?;
+        writeln!(out, "    mov edi, 2")?;
+        writeln!(out, "    lea rsi, [rip + .Lout_of_bounds_message]")?;
+        writeln!(out, "    mov edx, {}", OUT_OF_BOUNDS.len())?;
+        writeln!(out, "    syscall")?;
+        writeln!(out, "    mov eax, 60")?;
+        writeln!(out, "    mov edi, 1")?;
+        writeln!(out, "    syscall")?;
+        writeln!(out)?;
+
+        writeln!(out, "    .section .rodata")?;
+        writeln!(out, ".Lout_of_bounds_message:")?;
+        writeln!(out, "    .ascii \"{}\"", ascii(OUT_OF_BOUNDS))?;
+        for (index, bytes) in strings.iter().enumerate() {
+            writeln!(out, ".Lbytes{}:", index)?;
+            writeln!(out, "    .ascii \"{}\"", ascii(bytes))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "    .bss")?;
+        writeln!(out, "    .lcomm tape, {}", tape_size)
+    }
+}
+
+/// Generates the code of the nodes, collecting the strings of `OutputBytes`
+/// for `.rodata`
+struct Codegen<'a> {
+    out: &'a mut dyn Write,
+    labels: usize,
+    strings: Vec<Vec<u8>>,
+    checks: bool,
+}
+
+impl Codegen<'_> {
+    fn nodes(&mut self, nodes: &[Node]) -> io::Result<()> {
+        for node in nodes {
+            self.node(node)?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &Node) -> io::Result<()> {
+        self.check(node.offset())?;
+        let cell = cell(node.offset());
+
+        match node {
+            Node::Add { delta, .. } => writeln!(self.out, "    add byte ptr {}, {}", cell, delta),
+            Node::Set { value, .. } => writeln!(self.out, "    mov byte ptr {}, {}", cell, value),
+            Node::Move(n) => {
+                writeln!(self.out, "    add rbx, {}", n)?;
+                self.check_pointer(*n)
+            }
+            Node::Input { .. } => {
+                // EOF leaves a zero in the cell, like the interpreter
+                writeln!(self.out, "    mov byte ptr {}, 0", cell)?;
+                self.syscall(0, 0, &cell, 1)
+            }
+            Node::Output { .. } => self.syscall(1, 1, &cell, 1),
+            Node::OutputBytes(bytes) if bytes.is_empty() => Ok(()),
+            Node::OutputBytes(bytes) => {
+                let label = format!("[rip + .Lbytes{}]", self.strings.len());
+                self.strings.push(bytes.clone());
+                self.syscall(1, 1, &label, bytes.len())
+            }
+            Node::Scan(step) => {
+                let label = self.label();
+                writeln!(self.out, ".Lscan{}:", label)?;
+                writeln!(self.out, "    cmp byte ptr [rbx], 0")?;
+                writeln!(self.out, "    je .Lscan_end{}", label)?;
+                writeln!(self.out, "    add rbx, {}", step)?;
+                self.check_pointer(*step)?;
+                writeln!(self.out, "    jmp .Lscan{}", label)?;
+                writeln!(self.out, ".Lscan_end{}:", label)
+            }
+            Node::Loop(inner) => {
+                let label = self.label();
+                writeln!(self.out, "    cmp byte ptr [rbx], 0")?;
+                writeln!(self.out, "    je .Lend{}", label)?;
+                writeln!(self.out, ".Lbody{}:", label)?;
+                self.nodes(&inner.body)?;
+                writeln!(self.out, "    cmp byte ptr [rbx], 0")?;
+                writeln!(self.out, "    jne .Lbody{}", label)?;
+                writeln!(self.out, ".Lend{}:", label)
+            }
+            Node::If(body) => {
+                let label = self.label();
+                writeln!(self.out, "    cmp byte ptr [rbx], 0")?;
+                writeln!(self.out, "    je .Lend{}", label)?;
+                self.nodes(body)?;
+                writeln!(self.out, ".Lend{}:", label)
+            }
+        }
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    /// `read` or `write` of `len` bytes at `address`
+    fn syscall(&mut self, number: u8, fd: u8, address: &str, len: usize) -> io::Result<()> {
+        writeln!(self.out, "    mov eax, {}", number)?;
+        writeln!(self.out, "    mov edi, {}", fd)?;
+        writeln!(self.out, "    lea rsi, {}", address)?;
+        writeln!(self.out, "    mov edx, {}", len)?;
+        writeln!(self.out, "    syscall")
+    }
+
+    /// Checks the cell at `offset` from the pointer, which is already on the
+    /// tape, against the end of the tape in that direction
+    fn check(&mut self, offset: isize) -> io::Result<()> {
+        if offset == 0 || !self.checks {
+            return Ok(());
+        }
+        writeln!(self.out, "    lea rax, {}", cell(offset))?;
+        self.check_register("rax", offset)
+    }
+
+    /// Checks the pointer after moving it by `n`
+    fn check_pointer(&mut self, n: isize) -> io::Result<()> {
+        if !self.checks {
+            return Ok(());
+        }
+        self.check_register("rbx", n)
+    }
+
+    fn check_register(&mut self, register: &str, direction: isize) -> io::Result<()> {
+        if direction < 0 {
+            writeln!(self.out, "    cmp {}, r12", register)?;
+            writeln!(self.out, "    jb .Lout_of_bounds")
+        } else {
+            writeln!(self.out, "    cmp {}, r13", register)?;
+            writeln!(self.out, "    jae .Lout_of_bounds")
+        }
+    }
+}
+
+/// Memory operand of the cell at `offset` from the pointer
+fn cell(offset: isize) -> String {
+    match offset {
+        0 => String::from("[rbx]"),
+        ..=-1 => format!("[rbx - {}]", offset.unsigned_abs()),
+        _ => format!("[rbx + {}]", offset),
     }
 }
 
+/// `bytes` escaped for an `.ascii` directive
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     use super::*;
+    use crate::ir::lower;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{Optimizer, OptimizerOptions};
+    use crate::parser::{ASTNode, Parser};
+
+    fn compile(source: &str) -> Compiler {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        optimizer.optimize_with(&OptimizerOptions::default());
+        Compiler::new(optimizer.program)
+    }
+
+    fn assembly(compiler: &Compiler, tape_size: usize) -> String {
+        let mut out = Vec::new();
+        compiler.assembly(tape_size, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Assembles, links and runs `source`, `None` without `as` and `ld`
+    fn run(source: &str, tape_size: usize) -> Option<(i32, Vec<u8>, Vec<u8>)> {
+        let found = |tool| {
+            Command::new(tool)
+                .arg("--version")
+                .stdout(Stdio::null())
+                .status()
+                .is_ok()
+        };
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) || !found("as") || !found("ld") {
+            return None;
+        }
+
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "fucker-compiler-{}-{}",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (asm, object, binary) = (dir.join("a.s"), dir.join("a.o"), dir.join("a"));
+        std::fs::write(&asm, assembly(&compile(source), tape_size)).unwrap();
+
+        let assembled = Command::new("as").arg(&asm).arg("-o").arg(&object).status();
+        assert!(assembled.unwrap().success());
+        let linked = Command::new("ld")
+            .arg(&object)
+            .arg("-o")
+            .arg(&binary)
+            .status();
+        assert!(linked.unwrap().success());
+
+        let output = Command::new(&binary).stdin(Stdio::null()).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        Some((output.status.code().unwrap(), output.stdout, output.stderr))
+    }
 
     #[test]
     fn test_new() {
-        let nodes = vec![ASTNode::Incr(1)];
-        let compiler = Compiler::new(nodes);
+        let compiler = Compiler::new(lower(&[ASTNode::Next(1), ASTNode::Incr(1)]));
+
+        assert_eq!(
+            compiler.program,
+            vec![
+                Node::Add {
+                    offset: 1,
+                    delta: 1
+                },
+                Node::Move(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bounds_checks() {
+        let compiler = Compiler::new(lower(&[ASTNode::Next(2), ASTNode::Prev(1)]));
+        assert!(!compiler.needs_bounds_checks(3));
+        assert!(compiler.needs_bounds_checks(2));
+
+        let compiler = Compiler::new(vec![Node::Scan(1)]);
+        assert!(compiler.needs_bounds_checks(30000));
+
+        // only emitted when the pointer may leave the tape
+        let compiler = compile("+[->>+<<]>>[-<+>]<.");
+        assert!(!assembly(&compiler, 3).contains("jb .Lout_of_bounds"));
+        assert!(assembly(&compiler, 2).contains("jae .Lout_of_bounds"));
+        assert!(assembly(&compile(",[<]"), 30000).contains("jb .Lout_of_bounds"));
+    }
+
+    #[test]
+    fn test_output_bytes() {
+        // known output is one write of a constant string
+        let source = format!(",[-]{}.+.+.", "+".repeat(65));
+        let asm = assembly(&compile(&source), 30000);
+        assert_eq!(asm.matches("syscall").count(), 5);
+        assert!(asm.contains(".Lbytes0:\n    .ascii \"ABC\"\n"));
+        assert!(asm.contains("    lea rsi, [rip + .Lbytes0]\n    mov edx, 3\n"));
+
+        assert_eq!(ascii(b"a\"\\\n\xff"), "a\\\"\\\\\\012\\377");
+    }
+
+    #[test]
+    fn test_run() {
+        let Some((status, stdout, _)) = run("++++++++[>++++++++<-]>+.>+++[<+.>-]<<.,.", 30000)
+        else {
+            return;
+        };
+        assert_eq!(status, 0);
+        assert_eq!(stdout, b"ABCD\0\0");
+
+        let hello = std::fs::read_to_string("examples/hello.b").unwrap();
+        let (_, stdout, _) = run(&hello, 30000).unwrap();
+        assert_eq!(stdout, b"Hello World!\n");
+
+        let (status, _, stderr) = run("+[>+]", 16).unwrap();
+        assert_eq!(status, 1);
+        assert_eq!(stderr, OUT_OF_BOUNDS);
+        let (status, ..) = run(",<", 16).unwrap();
+        assert_eq!(status, 1);
 
-        assert_eq!(compiler.nodes, vec![ASTNode::Incr(1)]);
+        // the tape fits, so nothing is checked
+        let (status, stdout, _) = run("+++++[->++++++++++<]>-.", 2).unwrap();
+        assert_eq!((status, stdout), (0, b"1".to_vec()));
     }
 }
@@ -1 +1,3 @@
 mod compiler;
+
+pub use self::compiler::Compiler;
@@ -0,0 +1,227 @@
+use std::io::{self, Write};
+
+use colored::Colorize;
+
+use crate::interpreter::Instruction;
+use crate::lexer::Span;
+
+/// Times a loop was reached and how often its body ran
+struct LoopCoverage {
+    line: usize,
+    entries: u64,
+    iterations: u64,
+}
+
+/// Which source commands ran, built from the profile of an unoptimized program
+pub struct Coverage<'a> {
+    source: &'a str,
+    /// Execution count of every character, `None` for comments
+    hits: Vec<Option<u64>>,
+    loops: Vec<LoopCoverage>,
+}
+
+impl<'a> Coverage<'a> {
+    /// `spans` holds the source span of every instruction, see
+    /// `debugger::source_map`, and `counts` the profile of the program
+    pub fn new(source: &'a str, program: &[Instruction], spans: &[Span], counts: &[u64]) -> Self {
+        let mut hits = vec![None; source.chars().count()];
+        let mut loops = Vec::new();
+        let mut open = Vec::new();
+
+        for (index, span) in spans.iter().enumerate() {
+            hits[span.offset..span.offset + span.len].fill(Some(counts[index]));
+
+            match program[index] {
+                Instruction::BeginLoop(_) => {
+                    open.push(loops.len());
+                    loops.push(LoopCoverage {
+                        line: span.line,
+                        entries: counts[index],
+                        iterations: 0,
+                    });
+                }
+                Instruction::EndLoop(_) => {
+                    if let Some(current) = open.pop() {
+                        loops[current].iterations = counts[index];
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Coverage {
+            source,
+            hits,
+            loops,
+        }
+    }
+
+    /// Commands executed at least once, and all commands
+    pub fn commands(&self) -> (usize, usize) {
+        let commands = self.hits.iter().flatten();
+        (
+            commands.clone().filter(|&&count| count > 0).count(),
+            commands.count(),
+        )
+    }
+
+    /// Execution count of every line with commands, the most any of its
+    /// commands ran
+    fn lines(&self) -> Vec<(usize, u64)> {
+        let mut lines = Vec::new();
+        let mut hits = self.hits.iter();
+
+        for (index, line) in self.source.split('\n').enumerate() {
+            let count = hits.by_ref().take(line.chars().count() + 1).flatten().max();
+            if let Some(&count) = count {
+                lines.push((index + 1, count));
+            }
+        }
+        lines
+    }
+
+    /// Writes an lcov tracefile. Every loop is a branch whose body either
+    /// ran or didn't.
+    pub fn write_lcov(&self, path: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{}", path)?;
+
+        for (block, lp) in self.loops.iter().enumerate() {
+            match lp.entries {
+                0 => writeln!(out, "BRDA:{},{},0,-", lp.line, block)?,
+                _ => writeln!(out, "BRDA:{},{},0,{}", lp.line, block, lp.iterations)?,
+            }
+        }
+        let taken = self.loops.iter().filter(|lp| lp.iterations > 0).count();
+        writeln!(out, "BRF:{}", self.loops.len())?;
+        writeln!(out, "BRH:{}", taken)?;
+
+        let lines = self.lines();
+        for (line, count) in &lines {
+            writeln!(out, "DA:{},{}", line, count)?;
+        }
+        let hit = lines.iter().filter(|(_, count)| *count > 0).count();
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(out, "LH:{}", hit)?;
+        writeln!(out, "end_of_record")
+    }
+
+    /// Prints the source with executed commands in green and the rest in red
+    pub fn annotate(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut counts = self.lines().into_iter().peekable();
+        let mut hits = self.hits.iter();
+
+        for (index, line) in self.source.split('\n').enumerate() {
+            match counts.next_if(|(number, _)| *number == index + 1) {
+                Some((_, count)) => write!(out, "{:>10} | ", count)?,
+                None => write!(out, "{:>10} | ", "")?,
+            }
+
+            for (c, hit) in line.chars().zip(hits.by_ref()) {
+                let text = c.to_string();
+                match hit {
+                    Some(0) => write!(out, "{}", text.red().bold())?,
+                    Some(_) => write!(out, "{}", text.green())?,
+                    None => write!(out, "{}", text.dimmed())?,
+                }
+            }
+            // the newline
+            hits.next();
+            writeln!(out)?;
+        }
+
+        let (covered, total) = self.commands();
+        let percent = match total {
+            0 => 100.0,
+            total => covered as f64 * 100.0 / total as f64,
+        };
+        let skipped = self.loops.iter().filter(|lp| lp.iterations == 0).count();
+        writeln!(
+            out,
+            "\n{} of {} commands executed ({:.2}%), {} of {} loop bodies never ran",
+            covered,
+            total,
+            percent,
+            skipped,
+            self.loops.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::source_map;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lcov(source: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let spans = source_map(&ast.data, &ast.spans);
+
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_profiling(true);
+        interpreter.run().unwrap();
+
+        let coverage = Coverage::new(
+            source,
+            interpreter.program(),
+            &spans,
+            interpreter.profile().unwrap(),
+        );
+        let mut lcov = Vec::new();
+        coverage.write_lcov("test.b", &mut lcov).unwrap();
+        String::from_utf8(lcov).unwrap()
+    }
+
+    #[test]
+    fn test_commands() {
+        let source = "++ add two\n[-] clear\n[>+<-] never runs";
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let spans = source_map(&ast.data, &ast.spans);
+        let program = Interpreter::compile(ast.data);
+
+        // ++ [ - ] [ > + < - ]
+        let counts = [1, 1, 2, 2, 1, 0, 0, 0, 0, 0];
+        let coverage = Coverage::new(source, &program, &spans, &counts);
+
+        assert_eq!(coverage.commands(), (6, 11));
+        assert_eq!(coverage.lines(), [(1, 1), (2, 2), (3, 1)]);
+    }
+
+    #[test]
+    fn test_lcov() {
+        assert_eq!(
+            lcov("++\n[-]\n\n[>+<-]\n."),
+            "TN:\nSF:test.b\n\
+             BRDA:2,0,0,2\nBRDA:4,1,0,0\nBRF:2\nBRH:1\n\
+             DA:1,1\nDA:2,2\nDA:4,1\nDA:5,1\nLF:4\nLH:4\nend_of_record\n"
+        );
+
+        // the inner loop is never reached
+        assert!(lcov("[[-]]").contains("BRDA:1,0,0,0\nBRDA:1,1,0,-\n"));
+    }
+
+    #[test]
+    fn test_annotate() {
+        colored::control::set_override(false);
+        let source = "+[-]\nfoo >";
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let spans = source_map(&ast.data, &ast.spans);
+        let program = Interpreter::compile(ast.data);
+
+        let coverage = Coverage::new(source, &program, &spans, &[1, 1, 1, 1, 0]);
+        let mut view = Vec::new();
+        coverage.annotate(&mut view).unwrap();
+
+        assert_eq!(
+            String::from_utf8(view).unwrap(),
+            "         1 | +[-]\n         0 | foo >\n\n\
+             4 of 5 commands executed (80.00%), 0 of 1 loop bodies never ran\n"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+mod coverage;
+
+pub use self::coverage::Coverage;
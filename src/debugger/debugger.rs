@@ -0,0 +1,843 @@
+use std::io::{self, BufRead, Write};
+
+use colored::Colorize;
+
+use crate::interpreter::{Event, Instruction, Interpreter, Watchpoint};
+use crate::lexer::Span;
+use crate::parser::ASTNode;
+
+const DEFAULT_TAPE_RADIUS: usize = 8;
+
+const HELP: &str = "\
+commands:
+  s, step [n]               execute n instructions (default 1)
+  n, next                   step over a whole loop
+  c, continue               run until a breakpoint or the end of the program
+  rs, reverse-step [n]      undo n instructions (default 1)
+  rc, reverse-continue [cell]
+                            run backwards until a breakpoint or watchpoint, or
+                            to the last write of cell
+                            (both only go back as far as the recorded
+                            history, see `fucker debug --history`)
+  b, break <line>[:<col>]   break at a source position
+  b, break @<instruction>   break at an instruction index
+  d, delete [id]            delete a breakpoint, or all of them
+  watch read <cell>         stop when a cell is read
+  watch write <cell>        stop when a cell is written
+  watch value <cell> <v>    stop when a cell changes to v
+  watch pointer <a>..<b>    stop when the memory pointer enters a..b
+  unwatch [id]              delete a watchpoint, or all of them
+  i, info                   list breakpoints and watchpoints
+  t, tape [radius]          print the tape around the memory pointer
+  w, where                  print the current instruction
+  h, help                   print this message
+  q, quit                   exit the debugger
+every `#` in the source is a breakpoint, an empty line repeats the last command";
+
+/// Source span of every instruction `Interpreter::compile` produces for `ast`.
+/// Only meaningful for unoptimized trees, whose nodes still line up with the
+/// spans recorded by the parser.
+pub fn source_map(ast: &[ASTNode], spans: &[Span]) -> Vec<Span> {
+    fn walk<'s>(
+        nodes: &[ASTNode],
+        spans: &mut impl Iterator<Item = &'s Span>,
+        map: &mut Vec<Span>,
+    ) {
+        for node in nodes {
+            match node {
+                ASTNode::Loop(body) => {
+                    map.extend(spans.next());
+                    walk(body, spans, map);
+                    map.extend(spans.next());
+                }
+                ASTNode::Comment(_) => {
+                    spans.next();
+                }
+                ASTNode::NoOp => {}
+                _ => map.extend(spans.next()),
+            }
+        }
+    }
+
+    let mut map = Vec::new();
+    walk(ast, &mut spans.iter(), &mut map);
+    map
+}
+
+/// Index of the instruction following every `#` comment in `ast`
+pub fn hash_breakpoints(ast: &[ASTNode]) -> Vec<usize> {
+    fn walk(nodes: &[ASTNode], index: &mut usize, breakpoints: &mut Vec<usize>) {
+        for node in nodes {
+            match node {
+                ASTNode::Loop(body) => {
+                    *index += 1;
+                    walk(body, index, breakpoints);
+                    *index += 1;
+                }
+                ASTNode::Comment('#') => breakpoints.push(*index),
+                ASTNode::Comment(_) | ASTNode::NoOp => {}
+                _ => *index += 1,
+            }
+        }
+    }
+
+    let mut breakpoints = Vec::new();
+    walk(ast, &mut 0, &mut breakpoints);
+    breakpoints.dedup();
+    breakpoints
+}
+
+fn parse_watchpoint(arguments: &[&str]) -> Option<Watchpoint> {
+    match arguments {
+        ["read", cell] => Some(Watchpoint::Read(cell.parse().ok()?)),
+        ["write", cell] => Some(Watchpoint::Write(cell.parse().ok()?)),
+        ["value", cell, value] => Some(Watchpoint::Value {
+            cell: cell.parse().ok()?,
+            value: value.parse().ok()?,
+        }),
+        ["pointer", range] => {
+            let (start, end) = range.split_once("..")?;
+            Some(Watchpoint::Pointer {
+                start: start.parse().ok()?,
+                end: end.parse().ok()?,
+            })
+        }
+        _ => None,
+    }
+}
+
+struct Breakpoint {
+    id: usize,
+    program_counter: usize,
+}
+
+pub struct Debugger<'a> {
+    interpreter: Interpreter<'a>,
+    lines: Vec<String>,
+    spans: Option<Vec<Span>>,
+    hash_breakpoints: Vec<usize>,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: usize,
+    halted: bool,
+    /// Program output didn't end with a newline yet
+    partial_line: bool,
+}
+
+impl<'a> Debugger<'a> {
+    /// `program` is the tree `interpreter` was built from and `spans` maps its
+    /// instructions back to `source`, see `source_map`. Without it
+    /// only `#` and instruction breakpoints are available.
+    pub fn new(
+        source: &str,
+        program: &[ASTNode],
+        spans: Option<Vec<Span>>,
+        mut interpreter: Interpreter<'a>,
+    ) -> Self {
+        interpreter.set_journaling(true);
+
+        Debugger {
+            interpreter,
+            lines: source.lines().map(String::from).collect(),
+            spans,
+            hash_breakpoints: hash_breakpoints(program),
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            halted: false,
+            partial_line: false,
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+
+    /// Reads commands until `quit` or the end of `commands`. Program input is
+    /// prompted for on `commands` as well.
+    pub fn repl(&mut self, commands: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+        let mut last_command = String::new();
+
+        self.print_where(out)?;
+        loop {
+            write!(out, "{} ", "(fdb)".bold())?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if commands.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(());
+            }
+
+            let line = match line.trim() {
+                "" => last_command.clone(),
+                line => line.to_string(),
+            };
+            last_command.clone_from(&line);
+
+            if !self.command(&line, commands, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs a single command, returns false once the debugger should exit
+    pub fn command(
+        &mut self,
+        line: &str,
+        commands: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arguments: Vec<&str> = words.collect();
+        let argument = arguments.first().copied();
+
+        match command {
+            "" => {}
+            "s" | "step" => {
+                let count = match argument.map(str::parse::<usize>) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return self.usage(out, "step [n]"),
+                };
+
+                for _ in 0..count {
+                    if !self.step_once(commands, out)? || self.at_watchpoint(out)? {
+                        break;
+                    }
+                }
+                self.print_where(out)?;
+            }
+            "n" | "next" => {
+                let pc = self.interpreter.program_counter();
+                let target = match self.interpreter.program().get(pc) {
+                    Some(Instruction::BeginLoop(offset) | Instruction::If(offset)) => {
+                        pc + offset + 1
+                    }
+                    _ => pc + 1,
+                };
+
+                while self.step_once(commands, out)? {
+                    if self.interpreter.program_counter() == target || self.should_stop(out)? {
+                        break;
+                    }
+                }
+                self.print_where(out)?;
+            }
+            "c" | "continue" => {
+                while self.step_once(commands, out)? {
+                    if self.should_stop(out)? {
+                        break;
+                    }
+                }
+                self.print_where(out)?;
+            }
+            "rs" | "reverse-step" => {
+                let count = match argument.map(str::parse::<usize>) {
+                    None => 1,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return self.usage(out, "reverse-step [n]"),
+                };
+
+                for _ in 0..count {
+                    if !self.step_back(out)? {
+                        break;
+                    }
+                }
+                self.print_where(out)?;
+            }
+            "rc" | "reverse-continue" => {
+                let cell = match argument.map(str::parse::<usize>) {
+                    None => None,
+                    Some(Ok(cell)) => Some(cell),
+                    Some(Err(_)) => return self.usage(out, "reverse-continue [cell]"),
+                };
+
+                while let Some(entry) = self.interpreter.step_back() {
+                    self.halted = false;
+
+                    let written = entry.access.write.map(|(written, _, _)| written);
+                    if let Some(cell) = cell.filter(|cell| written == Some(*cell)) {
+                        self.end_output(out)?;
+                        writeln!(out, "last write to cell {}", cell)?;
+                        break;
+                    }
+
+                    let hits: Vec<_> = self
+                        .interpreter
+                        .watchpoints()
+                        .iter()
+                        .filter(|(_, watchpoint)| watchpoint.triggered_by(&entry.access))
+                        .map(|(id, watchpoint)| (*id, *watchpoint))
+                        .collect();
+                    if !hits.is_empty() {
+                        self.end_output(out)?;
+                        for (id, watchpoint) in hits {
+                            writeln!(out, "watchpoint {} ({})", id, watchpoint)?;
+                        }
+                        break;
+                    }
+
+                    if self.at_breakpoint(out)? {
+                        break;
+                    }
+                }
+
+                if self.interpreter.journal_len() == 0 {
+                    self.end_output(out)?;
+                    writeln!(out, "reached the start of the recorded history")?;
+                }
+                self.print_where(out)?;
+            }
+            "b" | "break" => match argument {
+                Some(location) => self.add_breakpoint(location, out)?,
+                None => return self.usage(out, "break <line>[:<column>] | break @<instruction>"),
+            },
+            "d" | "delete" => match argument.map(str::parse::<usize>) {
+                None => self.breakpoints.clear(),
+                Some(Ok(id)) => self.breakpoints.retain(|breakpoint| breakpoint.id != id),
+                Some(Err(_)) => return self.usage(out, "delete [id]"),
+            },
+            "watch" => {
+                match parse_watchpoint(&arguments) {
+                    Some(watchpoint) => {
+                        let id = self.interpreter.add_watchpoint(watchpoint);
+                        writeln!(out, "watchpoint {}: {}", id, watchpoint)?;
+                    }
+                    None => return self.usage(
+                        out,
+                        "watch read <cell> | write <cell> | value <cell> <v> | pointer <a>..<b>",
+                    ),
+                }
+            }
+            "unwatch" => match argument.map(str::parse::<usize>) {
+                None => {
+                    let ids: Vec<_> = self
+                        .interpreter
+                        .watchpoints()
+                        .iter()
+                        .map(|(id, _)| *id)
+                        .collect();
+                    for id in ids {
+                        self.interpreter.remove_watchpoint(id);
+                    }
+                }
+                Some(Ok(id)) => {
+                    if !self.interpreter.remove_watchpoint(id) {
+                        writeln!(out, "there is no watchpoint {}", id)?;
+                    }
+                }
+                Some(Err(_)) => return self.usage(out, "unwatch [id]"),
+            },
+            "i" | "info" => {
+                for breakpoint in &self.breakpoints {
+                    writeln!(
+                        out,
+                        "breakpoint {} at {}",
+                        breakpoint.id,
+                        self.describe(breakpoint.program_counter)
+                    )?;
+                }
+                if !self.hash_breakpoints.is_empty() {
+                    writeln!(out, "{} `#` breakpoints", self.hash_breakpoints.len())?;
+                }
+                for (id, watchpoint) in self.interpreter.watchpoints() {
+                    writeln!(out, "watchpoint {}: {}", id, watchpoint)?;
+                }
+            }
+            "t" | "tape" => match argument.map(str::parse::<usize>) {
+                None => self.print_tape(DEFAULT_TAPE_RADIUS, out)?,
+                Some(Ok(radius)) => self.print_tape(radius, out)?,
+                Some(Err(_)) => return self.usage(out, "tape [radius]"),
+            },
+            "w" | "where" => self.print_where(out)?,
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(out, "unknown command `{}`, try `help`", command)?,
+        }
+
+        Ok(true)
+    }
+
+    fn usage(&self, out: &mut dyn Write, usage: &str) -> io::Result<bool> {
+        writeln!(out, "usage: {}", usage)?;
+        Ok(true)
+    }
+
+    /// Executes one instruction, returns false if the program can't continue
+    fn step_once(&mut self, commands: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<bool> {
+        if self.halted {
+            return Ok(false);
+        }
+
+        loop {
+            match self.interpreter.step() {
+                Ok(Event::Paused) => break,
+                Ok(Event::Output(byte)) => {
+                    out.write_all(&[byte])?;
+                    self.partial_line = byte != b'\n';
+                    break;
+                }
+                Ok(Event::NeedsInput) => {
+                    self.end_output(out)?;
+                    write!(out, "{} ", "input>".bold())?;
+                    out.flush()?;
+
+                    let mut line = String::new();
+                    if commands.read_line(&mut line)? == 0 {
+                        self.interpreter.close_input();
+                    }
+                    self.interpreter.push_input(line.as_bytes());
+                }
+                Ok(Event::Halted) => {
+                    self.halted = true;
+                    return Ok(false);
+                }
+                Err(err) => {
+                    self.end_output(out)?;
+                    writeln!(out, "{}", format!("program stopped: {}", err).red().bold())?;
+                    self.halted = true;
+                    return Ok(false);
+                }
+            }
+        }
+
+        // the last instruction was an output, there is nothing left to run
+        if self.interpreter.program_counter() >= self.interpreter.program().len() {
+            self.halted = true;
+        }
+        Ok(true)
+    }
+
+    /// Undoes one instruction, returns false at the start of the recorded history
+    fn step_back(&mut self, out: &mut dyn Write) -> io::Result<bool> {
+        if self.interpreter.step_back().is_none() {
+            self.end_output(out)?;
+            writeln!(out, "reached the start of the recorded history")?;
+            return Ok(false);
+        }
+
+        self.halted = false;
+        Ok(true)
+    }
+
+    fn should_stop(&mut self, out: &mut dyn Write) -> io::Result<bool> {
+        Ok(self.at_watchpoint(out)? | self.at_breakpoint(out)?)
+    }
+
+    /// Reports the watchpoints the last step triggered
+    fn at_watchpoint(&mut self, out: &mut dyn Write) -> io::Result<bool> {
+        let Some(access) = self.interpreter.last_access().copied() else {
+            return Ok(false);
+        };
+        let hits = self.interpreter.watch_hits().to_vec();
+        if hits.is_empty() {
+            return Ok(false);
+        }
+
+        self.end_output(out)?;
+        for id in hits {
+            let watchpoint = self
+                .interpreter
+                .watchpoints()
+                .iter()
+                .find(|(watchpoint_id, _)| *watchpoint_id == id)
+                .map(|(_, watchpoint)| *watchpoint);
+
+            write!(out, "watchpoint {}", id)?;
+            if let Some(watchpoint) = watchpoint {
+                write!(out, " ({})", watchpoint)?;
+            }
+            write!(out, " hit by {}", self.describe(access.program_counter))?;
+            if let Some((cell, old, new)) = access.write {
+                write!(out, ", cell {}: {} -> {}", cell, old, new)?;
+            }
+            writeln!(out)?;
+        }
+
+        Ok(true)
+    }
+
+    fn at_breakpoint(&mut self, out: &mut dyn Write) -> io::Result<bool> {
+        let pc = self.interpreter.program_counter();
+
+        let hit = self.breakpoints.iter().find(|b| b.program_counter == pc);
+        if let Some(id) = hit.map(|breakpoint| breakpoint.id) {
+            self.end_output(out)?;
+            writeln!(out, "breakpoint {}", id)?;
+            return Ok(true);
+        }
+        if self.hash_breakpoints.contains(&pc) {
+            self.end_output(out)?;
+            writeln!(out, "breakpoint `#`")?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn add_breakpoint(&mut self, location: &str, out: &mut dyn Write) -> io::Result<()> {
+        match self.resolve(location) {
+            Ok(program_counter) => {
+                let id = self.next_breakpoint_id;
+                self.next_breakpoint_id += 1;
+                self.breakpoints.push(Breakpoint {
+                    id,
+                    program_counter,
+                });
+
+                writeln!(
+                    out,
+                    "breakpoint {} at {}",
+                    id,
+                    self.describe(program_counter)
+                )
+            }
+            Err(err) => writeln!(out, "{}", err),
+        }
+    }
+
+    /// Finds the instruction a breakpoint location refers to
+    fn resolve(&self, location: &str) -> Result<usize, String> {
+        let invalid = || format!("invalid breakpoint location `{}`", location);
+
+        if let Some(index) = location.strip_prefix('@') {
+            let index: usize = index.parse().map_err(|_| invalid())?;
+            if index >= self.interpreter.program().len() {
+                return Err(format!("there is no instruction @{}", index));
+            }
+            return Ok(index);
+        }
+
+        let Some(spans) = &self.spans else {
+            return Err(String::from(
+                "source positions are not available for optimized programs, use @<instruction>",
+            ));
+        };
+
+        let (line, column) = match location.split_once(':') {
+            Some((line, column)) => (line, column.parse().map_err(|_| invalid())?),
+            None => (location, 1),
+        };
+        let line: usize = line.parse().map_err(|_| invalid())?;
+
+        spans
+            .iter()
+            .position(|span| span.line == line && span.column + span.len > column)
+            .ok_or_else(|| format!("there is no instruction at {}:{}", line, column))
+    }
+
+    fn describe(&self, program_counter: usize) -> String {
+        let instruction = match self.interpreter.program().get(program_counter) {
+            Some(instruction) => format!("@{} {:?}", program_counter, instruction),
+            None => format!("@{} <end>", program_counter),
+        };
+
+        match self
+            .spans
+            .as_ref()
+            .and_then(|spans| spans.get(program_counter))
+        {
+            Some(span) => format!(
+                "{} (line {}, column {})",
+                instruction, span.line, span.column
+            ),
+            None => instruction,
+        }
+    }
+
+    /// Starts a new line if the program left the cursor in the middle of one
+    fn end_output(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.partial_line {
+            writeln!(out)?;
+            self.partial_line = false;
+        }
+        Ok(())
+    }
+
+    fn print_where(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.end_output(out)?;
+
+        let pc = self.interpreter.program_counter();
+        if self.halted || pc >= self.interpreter.program().len() {
+            return writeln!(
+                out,
+                "program halted after {} steps",
+                self.interpreter.steps()
+            );
+        }
+
+        writeln!(out, "{}", self.describe(pc))?;
+
+        let span = self.spans.as_ref().and_then(|spans| spans.get(pc));
+        if let Some(line) = span.and_then(|span| self.lines.get(span.line - 1)) {
+            let span = span.unwrap();
+            let before: String = line.chars().take(span.column - 1).collect();
+            let current: String = line.chars().skip(span.column - 1).take(span.len).collect();
+            let after: String = line.chars().skip(span.column - 1 + span.len).collect();
+
+            writeln!(
+                out,
+                "{:>5} | {}{}{}",
+                span.line,
+                before,
+                current.red().bold(),
+                after
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn print_tape(&self, radius: usize, out: &mut dyn Write) -> io::Result<()> {
+        let memory = self.interpreter.memory();
+        let pointer = self.interpreter.memory_pointer();
+        let start = pointer.saturating_sub(radius);
+        let end = (pointer + radius + 1).min(memory.len());
+
+        let mut cells = String::from(" cell ");
+        let mut values = String::from("value ");
+        let mut marker = String::from("      ");
+        for (index, value) in memory.iter().enumerate().take(end).skip(start) {
+            cells.push_str(&format!("{:>6}", index));
+            values.push_str(&format!("{:>6}", value));
+            marker.push_str(if index == pointer { "     ^" } else { "      " });
+        }
+
+        writeln!(out, "{}", cells)?;
+        writeln!(out, "{}", values)?;
+        writeln!(out, "{}", marker.trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn debugger(source: &str) -> Debugger<'static> {
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let spans = source_map(&ast.data, &ast.spans);
+
+        let interpreter = Interpreter::new(ast.data.clone());
+
+        Debugger::new(source, &ast.data, Some(spans), interpreter)
+    }
+
+    fn run(debugger: &mut Debugger, commands: &str) -> String {
+        let mut out = Vec::new();
+        debugger.repl(&mut commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "+ [\n-]";
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+
+        let positions: Vec<_> = source_map(&ast.data, &ast.spans)
+            .iter()
+            .map(|span| (span.line, span.column))
+            .collect();
+
+        assert_eq!(positions, vec![(1, 1), (1, 3), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_hash_breakpoints() {
+        let mut parser = Parser::new(Lexer::new(String::from("+#[>#<]#")));
+        let ast = parser.parse_helper();
+
+        assert_eq!(hash_breakpoints(&ast.data), vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut debugger = debugger("+++>+");
+
+        run(&mut debugger, "step\nstep\n");
+        assert_eq!(debugger.interpreter().program_counter(), 2);
+        assert_eq!(debugger.interpreter().memory()[..2], [3, 0]);
+
+        run(&mut debugger, "step 5\n");
+        assert!(debugger.halted);
+    }
+
+    #[test]
+    fn test_continue_to_hash() {
+        let mut debugger = debugger("+#+#+");
+
+        run(&mut debugger, "c\n");
+        assert_eq!(debugger.interpreter().memory()[0], 1);
+
+        // an empty line repeats the last command
+        run(&mut debugger, "c\n\n");
+        assert_eq!(debugger.interpreter().memory()[0], 3);
+        assert!(debugger.halted);
+    }
+
+    #[test]
+    fn test_break_at_source_position() {
+        let mut debugger = debugger("++++[>+<-]\n>>+\n");
+
+        let out = run(&mut debugger, "break 2\nbreak 1:7\ncontinue\n");
+        assert!(out.contains("breakpoint 1 at @7 Next(2) (line 2, column 1)"));
+        assert!(out.contains("breakpoint 2 at @3 Incr(1) (line 1, column 7)"));
+        assert_eq!(debugger.interpreter().program_counter(), 3);
+
+        run(&mut debugger, "delete 2\ncontinue\n");
+        assert_eq!(debugger.interpreter().program_counter(), 7);
+        assert_eq!(debugger.interpreter().memory()[1], 4);
+
+        let out = run(&mut debugger, "break 9\n");
+        assert!(out.contains("there is no instruction at 9:1"));
+    }
+
+    #[test]
+    fn test_next_steps_over_loops() {
+        let mut debugger = debugger("++[>+<-]>.");
+
+        run(&mut debugger, "next\nnext\n");
+        assert_eq!(debugger.interpreter().program_counter(), 7);
+        assert_eq!(debugger.interpreter().memory()[1], 2);
+
+        let out = run(&mut debugger, "n\nn\n");
+        assert!(out.contains('\u{2}'));
+        assert!(debugger.halted);
+    }
+
+    #[test]
+    fn test_input_prompt() {
+        let mut debugger = debugger(",.");
+
+        let out = run(&mut debugger, "s\nA\ns\n");
+        assert!(out.contains("input>"));
+        assert!(out.contains("A\n"));
+        assert_eq!(debugger.interpreter().memory()[0], b'A');
+    }
+
+    #[test]
+    fn test_instruction_breakpoint_without_spans() {
+        let mut parser = Parser::new(Lexer::new(String::from("+>+")));
+        let ast = parser.parse_helper();
+        let mut debugger = Debugger::new("", &[], None, Interpreter::new(ast.data));
+
+        let out = run(&mut debugger, "break 1:1\n");
+        assert!(out.contains("not available"));
+
+        let out = run(&mut debugger, "break @1\n");
+        assert!(out.contains("breakpoint 1 at @1"));
+    }
+
+    #[test]
+    fn test_parse_watchpoint() {
+        assert_eq!(parse_watchpoint(&["read", "3"]), Some(Watchpoint::Read(3)));
+        assert_eq!(
+            parse_watchpoint(&["write", "3"]),
+            Some(Watchpoint::Write(3))
+        );
+        assert_eq!(
+            parse_watchpoint(&["value", "3", "255"]),
+            Some(Watchpoint::Value {
+                cell: 3,
+                value: 255
+            })
+        );
+        assert_eq!(
+            parse_watchpoint(&["pointer", "10..20"]),
+            Some(Watchpoint::Pointer { start: 10, end: 20 })
+        );
+        assert_eq!(parse_watchpoint(&["value", "3", "256"]), None);
+        assert_eq!(parse_watchpoint(&["pointer", "10"]), None);
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut debugger = debugger("+++[>++<-]>>+<[-]");
+
+        let out = run(&mut debugger, "watch value 1 4\ncontinue\n");
+        assert!(out.contains("watchpoint 1: cell 1 becoming 4"));
+        assert!(out.contains("watchpoint 1 (cell 1 becoming 4) hit by @3 Incr(2)"));
+        assert!(out.contains("cell 1: 2 -> 4"));
+        assert_eq!(debugger.interpreter().memory()[..2], [2, 4]);
+
+        let out = run(&mut debugger, "unwatch\nwatch write 1\nc\nc\n");
+        assert!(out.contains("watchpoint 2 (write to cell 1) hit by @3 Incr(2)"));
+        assert!(out.contains("watchpoint 2 (write to cell 1) hit by @11 Decr(1)"));
+        assert_eq!(debugger.interpreter().memory()[..3], [0, 5, 1]);
+
+        let out = run(&mut debugger, "info\nunwatch 2\nunwatch 2\n");
+        assert!(out.contains("watchpoint 2: write to cell 1"));
+        assert!(out.contains("there is no watchpoint 2"));
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let mut debugger = debugger("+>++>+++");
+
+        run(&mut debugger, "c\n");
+        assert!(debugger.halted);
+
+        run(&mut debugger, "rs 2\n");
+        assert!(!debugger.halted);
+        assert_eq!(debugger.interpreter().program_counter(), 3);
+        assert_eq!(debugger.interpreter().memory()[..3], [1, 2, 0]);
+
+        let out = run(&mut debugger, "rs 10\n");
+        assert!(out.contains("reached the start of the recorded history"));
+        assert_eq!(debugger.interpreter().program_counter(), 0);
+        assert_eq!(debugger.interpreter().memory()[..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_reverse_step_limit() {
+        let mut parser = Parser::new(Lexer::new(String::from("+>++>+++")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data.clone());
+        interpreter.set_journal_limit(3);
+        let mut debugger = Debugger::new("+>++>+++", &ast.data, None, interpreter);
+
+        run(&mut debugger, "c\n");
+        let out = run(&mut debugger, "rs 10\n");
+        assert!(out.contains("reached the start of the recorded history"));
+        assert_eq!(debugger.interpreter().program_counter(), 2);
+        assert_eq!(debugger.interpreter().memory()[..3], [1, 0, 0]);
+    }
+
+    #[test]
+    fn test_reverse_continue() {
+        let mut debugger = debugger("++[>+>+<<-]>>[-]<<");
+
+        run(&mut debugger, "c\n");
+        assert_eq!(debugger.interpreter().memory()[..3], [0, 2, 0]);
+
+        // the last write of cell 2 is the final `-` of the clear loop
+        let out = run(&mut debugger, "rc 2\n");
+        assert!(out.contains("last write to cell 2"));
+        assert_eq!(debugger.interpreter().program_counter(), 11);
+        assert_eq!(debugger.interpreter().memory()[2], 1);
+
+        // watchpoints work backwards as well
+        let out = run(&mut debugger, "watch value 1 1\nrc\n");
+        assert!(out.contains("watchpoint 1 (cell 1 becoming 1)"));
+        assert_eq!(debugger.interpreter().program_counter(), 3);
+        assert_eq!(debugger.interpreter().memory()[..3], [2, 0, 0]);
+
+        // and forwards again from there
+        run(&mut debugger, "unwatch\nc\n");
+        assert_eq!(debugger.interpreter().memory()[..3], [0, 2, 0]);
+    }
+
+    #[test]
+    fn test_tape() {
+        let mut debugger = debugger(">>+++");
+
+        run(&mut debugger, "s 2\n");
+        let out = run(&mut debugger, "tape 1\n");
+
+        assert!(out.contains(" cell      1     2     3"));
+        assert!(out.contains("value      0     3     0"));
+    }
+}
@@ -0,0 +1,3 @@
+mod debugger;
+
+pub use self::debugger::{hash_breakpoints, source_map, Debugger};
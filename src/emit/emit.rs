@@ -0,0 +1,471 @@
+use std::io::{self, Write};
+
+use crate::interpreter::Instruction;
+use crate::ir::Node;
+use crate::lexer::{Lexer, Token};
+use crate::parser::ASTNode;
+
+/// Pipeline stages `--emit` can print
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Stage {
+    /// What `Lexer` reads, with line and column
+    Tokens,
+    /// What `Parser` builds
+    Ast,
+    /// The IR after `Optimizer`, also accepted as `optimized-ast`
+    #[value(alias = "optimized-ast")]
+    OptimizedIr,
+    /// What actually runs, after compiling and the instruction passes
+    Instructions,
+    /// x86-64 Linux assembly from `compiler::Compiler`, text only
+    Asm,
+}
+
+/// Output formats of `--emit`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmitFormat {
+    /// One item per line, loop bodies indented
+    #[default]
+    Text,
+    /// A single JSON array
+    Json,
+}
+
+/// JSON string literal for `text`
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Every token of `source`, comments included
+pub fn tokens(source: &str, format: EmitFormat, out: &mut dyn Write) -> io::Result<()> {
+    let mut lexer = Lexer::new(String::from(source));
+    let mut first = true;
+
+    if format == EmitFormat::Json {
+        write!(out, "[")?;
+    }
+    loop {
+        let span = lexer.start_span();
+        let Some(token) = lexer.next_token() else {
+            break;
+        };
+
+        match format {
+            EmitFormat::Text => writeln!(out, "{}:{} {:?}", span.line, span.column, token)?,
+            EmitFormat::Json => {
+                let name = match token {
+                    Token::Other(_) => String::from("Other"),
+                    ref token => format!("{:?}", token),
+                };
+                write!(
+                    out,
+                    "{}{{\"token\":\"{}\",\"line\":{},\"column\":{}",
+                    if first { "" } else { "," },
+                    name,
+                    span.line,
+                    span.column
+                )?;
+                if let Token::Other(c) = token {
+                    write!(out, ",\"char\":{}", json_string(&c.to_string()))?;
+                }
+                write!(out, "}}")?;
+            }
+        }
+        first = false;
+    }
+    if format == EmitFormat::Json {
+        writeln!(out, "]")?;
+    }
+    Ok(())
+}
+
+/// A parsed tree, loop bodies nested in their node
+pub fn ast(nodes: &[ASTNode], format: EmitFormat, out: &mut dyn Write) -> io::Result<()> {
+    match format {
+        EmitFormat::Text => ast_text(nodes, 0, out),
+        EmitFormat::Json => {
+            ast_json(nodes, out)?;
+            writeln!(out)
+        }
+    }
+}
+
+fn ast_text(nodes: &[ASTNode], depth: usize, out: &mut dyn Write) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match node {
+            ASTNode::Loop(body) => {
+                writeln!(out, "{}{}", indent, node_name(node))?;
+                ast_text(body, depth + 1, out)?;
+            }
+            node => writeln!(out, "{}{:?}", indent, node)?,
+        }
+    }
+    Ok(())
+}
+
+fn ast_json(nodes: &[ASTNode], out: &mut dyn Write) -> io::Result<()> {
+    write!(out, "[")?;
+    for (index, node) in nodes.iter().enumerate() {
+        if index > 0 {
+            write!(out, ",")?;
+        }
+        let (name, value) = match node {
+            ASTNode::Incr(n) | ASTNode::Decr(n) => (node_name(node), Some(n.to_string())),
+            ASTNode::Next(n) | ASTNode::Prev(n) => (node_name(node), Some(n.to_string())),
+            ASTNode::Comment(c) => ("Comment", Some(json_string(&c.to_string()))),
+            ASTNode::Loop(body) => {
+                write!(out, "{{\"node\":\"{}\",\"body\":", node_name(node))?;
+                ast_json(body, out)?;
+                write!(out, "}}")?;
+                continue;
+            }
+            node => (node_name(node), None),
+        };
+        write!(out, "{{\"node\":\"{}\"", name)?;
+        if let Some(value) = value {
+            write!(out, ",\"value\":{}", value)?;
+        }
+        write!(out, "}}")?;
+    }
+    write!(out, "]")
+}
+
+fn node_name(node: &ASTNode) -> &'static str {
+    match node {
+        ASTNode::Incr(_) => "Incr",
+        ASTNode::Decr(_) => "Decr",
+        ASTNode::Next(_) => "Next",
+        ASTNode::Prev(_) => "Prev",
+        ASTNode::Loop(_) => "Loop",
+        ASTNode::Input => "Input",
+        ASTNode::Output => "Output",
+        ASTNode::Comment(_) => "Comment",
+        ASTNode::NoOp => "NoOp",
+    }
+}
+
+/// Optimized IR, bodies of `Loop` and `If` nested in their node. Cells away
+/// from the pointer show their offset.
+pub fn ir(nodes: &[Node], format: EmitFormat, out: &mut dyn Write) -> io::Result<()> {
+    match format {
+        EmitFormat::Text => ir_text(nodes, 0, out),
+        EmitFormat::Json => {
+            ir_json(nodes, out)?;
+            writeln!(out)
+        }
+    }
+}
+
+fn ir_text(nodes: &[Node], depth: usize, out: &mut dyn Write) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        write!(out, "{}", indent)?;
+        match node {
+            Node::Add { delta, .. } => write!(out, "Add({:+})", delta)?,
+            Node::Set { value, .. } => write!(out, "Set({})", value)?,
+            Node::Move(n) => write!(out, "Move({:+})", n)?,
+            Node::Input { .. } => write!(out, "Input")?,
+            Node::Output { .. } => write!(out, "Output")?,
+            Node::OutputBytes(bytes) => write!(out, "OutputBytes(\"{}\")", bytes.escape_ascii())?,
+            Node::Scan(step) => write!(out, "Scan({:+})", step)?,
+            Node::Loop(inner) => {
+                match inner.shift {
+                    Some(0) => writeln!(out, "Loop balanced")?,
+                    Some(shift) => writeln!(out, "Loop shift {:+}", shift)?,
+                    None => writeln!(out, "Loop shift ?")?,
+                }
+                ir_text(&inner.body, depth + 1, out)?;
+                continue;
+            }
+            Node::If(body) => {
+                writeln!(out, "If")?;
+                ir_text(body, depth + 1, out)?;
+                continue;
+            }
+        }
+        if node.offset() != 0 {
+            write!(out, " at {:+}", node.offset())?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn ir_json(nodes: &[Node], out: &mut dyn Write) -> io::Result<()> {
+    write!(out, "[")?;
+    for (index, node) in nodes.iter().enumerate() {
+        if index > 0 {
+            write!(out, ",")?;
+        }
+        match node {
+            Node::Add { offset, delta } => write!(
+                out,
+                "{{\"node\":\"Add\",\"offset\":{},\"delta\":{}}}",
+                offset, delta
+            )?,
+            Node::Set { offset, value } => write!(
+                out,
+                "{{\"node\":\"Set\",\"offset\":{},\"value\":{}}}",
+                offset, value
+            )?,
+            Node::Move(n) => write!(out, "{{\"node\":\"Move\",\"value\":{}}}", n)?,
+            Node::Input { offset } => write!(out, "{{\"node\":\"Input\",\"offset\":{}}}", offset)?,
+            Node::Output { offset } => {
+                write!(out, "{{\"node\":\"Output\",\"offset\":{}}}", offset)?
+            }
+            Node::OutputBytes(bytes) => {
+                let bytes: Vec<_> = bytes.iter().map(u8::to_string).collect();
+                write!(
+                    out,
+                    "{{\"node\":\"OutputBytes\",\"bytes\":[{}]}}",
+                    bytes.join(",")
+                )?;
+            }
+            Node::Scan(step) => write!(out, "{{\"node\":\"Scan\",\"step\":{}}}", step)?,
+            Node::Loop(inner) => {
+                let shift = inner.shift.map_or(String::from("null"), |s| s.to_string());
+                write!(out, "{{\"node\":\"Loop\",\"shift\":{},\"body\":", shift)?;
+                ir_json(&inner.body, out)?;
+                write!(out, "}}")?;
+            }
+            Node::If(body) => {
+                write!(out, "{{\"node\":\"If\",\"body\":")?;
+                ir_json(body, out)?;
+                write!(out, "}}")?;
+            }
+        }
+    }
+    write!(out, "]")
+}
+
+/// Name and operands of an instruction, leaving out jump offsets
+fn operands(instruction: &Instruction) -> (&'static str, Vec<usize>) {
+    match *instruction {
+        Instruction::Incr(n) => ("Incr", vec![n as usize]),
+        Instruction::Decr(n) => ("Decr", vec![n as usize]),
+        Instruction::Next(n) => ("Next", vec![n]),
+        Instruction::Prev(n) => ("Prev", vec![n]),
+        Instruction::Input => ("Input", vec![]),
+        Instruction::Output => ("Output", vec![]),
+        Instruction::BeginLoop(_) => ("BeginLoop", vec![]),
+        Instruction::EndLoop(_) => ("EndLoop", vec![]),
+        Instruction::ScanRight => ("ScanRight", vec![]),
+        Instruction::ScanLeft => ("ScanLeft", vec![]),
+        Instruction::Set(n) => ("Set", vec![n as usize]),
+        Instruction::SetNext(n, k) => ("SetNext", vec![n as usize, k]),
+        Instruction::SetPrev(n, k) => ("SetPrev", vec![n as usize, k]),
+        Instruction::NextEndLoop(k, _) => ("NextEndLoop", vec![k]),
+        Instruction::PrevEndLoop(k, _) => ("PrevEndLoop", vec![k]),
+        Instruction::DecrEndLoop(n, _) => ("DecrEndLoop", vec![n as usize]),
+        Instruction::NextScanRight(k) => ("NextScanRight", vec![k]),
+        Instruction::PrevScanLeft(k) => ("PrevScanLeft", vec![k]),
+        Instruction::OutputBytes(_) => ("OutputBytes", vec![]),
+        Instruction::If(_) => ("If", vec![]),
+    }
+}
+
+/// Index execution continues at when the instruction jumps
+fn jump_target(index: usize, instruction: &Instruction) -> Option<usize> {
+    match *instruction {
+        Instruction::BeginLoop(offset) | Instruction::If(offset) => Some(index + offset + 1),
+        ref instruction => instruction.end_loop().map(|offset| index - offset + 1),
+    }
+}
+
+/// Compiled instructions with their index and absolute jump targets, loop and
+/// `If` bodies indented in text
+pub fn instructions(
+    program: &[Instruction],
+    format: EmitFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if format == EmitFormat::Json {
+        write!(out, "[")?;
+    }
+    let mut loops = 0;
+    // where the bodies of the `If`s around the current instruction end
+    let mut ifs: Vec<usize> = Vec::new();
+
+    for (index, instruction) in program.iter().enumerate() {
+        while ifs.last() == Some(&index) {
+            ifs.pop();
+        }
+        if instruction.end_loop().is_some() {
+            loops -= 1;
+        }
+
+        let (name, operands) = operands(instruction);
+        let target = jump_target(index, instruction);
+        match format {
+            EmitFormat::Text => {
+                let indent = "  ".repeat(loops + ifs.len());
+                write!(out, "{:>5}  {}{}", index, indent, name)?;
+                match instruction {
+                    Instruction::OutputBytes(bytes) => {
+                        write!(out, "(\"{}\")", bytes.escape_ascii())?
+                    }
+                    _ if !operands.is_empty() => {
+                        let operands: Vec<_> = operands.iter().map(usize::to_string).collect();
+                        write!(out, "({})", operands.join(", "))?;
+                    }
+                    _ => {}
+                }
+                if let Some(target) = target {
+                    write!(out, " -> {}", target)?;
+                }
+                writeln!(out)?;
+            }
+            EmitFormat::Json => {
+                if index > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "{{\"index\":{},\"instruction\":\"{}\"", index, name)?;
+                match instruction {
+                    Instruction::OutputBytes(bytes) => {
+                        let bytes: Vec<_> = bytes.iter().map(u8::to_string).collect();
+                        write!(out, ",\"operands\":[{}]", bytes.join(","))?;
+                    }
+                    _ => {
+                        let operands: Vec<_> = operands.iter().map(usize::to_string).collect();
+                        write!(out, ",\"operands\":[{}]", operands.join(","))?;
+                    }
+                }
+                if let Some(target) = target {
+                    write!(out, ",\"target\":{}", target)?;
+                }
+                write!(out, "}}")?;
+            }
+        }
+
+        match *instruction {
+            Instruction::BeginLoop(_) => loops += 1,
+            Instruction::If(offset) => ifs.push(index + offset + 1),
+            _ => {}
+        }
+    }
+    if format == EmitFormat::Json {
+        writeln!(out, "]")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::ir::lower;
+    use crate::optimizer::fold_offsets;
+    use crate::parser::Parser;
+
+    fn emit(f: impl Fn(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn parse(source: &str) -> Vec<ASTNode> {
+        Parser::new(Lexer::new(String::from(source)))
+            .parse_helper()
+            .data
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            emit(|out| tokens("+\n\"", EmitFormat::Text, out)),
+            "1:1 Incr\n1:2 Other('\\n')\n2:1 Other('\"')\n"
+        );
+        assert_eq!(
+            emit(|out| tokens("[\"", EmitFormat::Json, out)),
+            "[{\"token\":\"LoopStart\",\"line\":1,\"column\":1},\
+             {\"token\":\"Other\",\"line\":1,\"column\":2,\"char\":\"\\\"\"}]\n"
+        );
+    }
+
+    #[test]
+    fn test_ast() {
+        let nodes = parse("++[->[-]<]a");
+        assert_eq!(
+            emit(|out| ast(&nodes, EmitFormat::Text, out)),
+            "Incr(2)\nLoop\n  Decr(1)\n  Next(1)\n  Loop\n    Decr(1)\n  Prev(1)\nComment('a')\n"
+        );
+        assert_eq!(
+            emit(|out| ast(&nodes[1..2], EmitFormat::Json, out)),
+            "[{\"node\":\"Loop\",\"body\":[{\"node\":\"Decr\",\"value\":1},\
+             {\"node\":\"Next\",\"value\":1},{\"node\":\"Loop\",\"body\":\
+             [{\"node\":\"Decr\",\"value\":1}]},{\"node\":\"Prev\",\"value\":1}]}]\n"
+        );
+    }
+
+    #[test]
+    fn test_ir() {
+        let nodes = fold_offsets(lower(&parse(",[>+<-]>>.<<[>]")));
+        assert_eq!(
+            emit(|out| ir(&nodes, EmitFormat::Text, out)),
+            "Input\nLoop balanced\n  Add(+1) at +1\n  Add(-1)\nOutput at +2\nLoop shift +1\n  Move(+1)\n"
+        );
+        assert_eq!(
+            emit(|out| ir(&nodes[..2], EmitFormat::Json, out)),
+            "[{\"node\":\"Input\",\"offset\":0},{\"node\":\"Loop\",\"shift\":0,\"body\":\
+             [{\"node\":\"Add\",\"offset\":1,\"delta\":1},\
+             {\"node\":\"Add\",\"offset\":0,\"delta\":-1}]}]\n"
+        );
+        assert_eq!(
+            emit(|out| ir(
+                &[Node::OutputBytes(b"a\n".to_vec()), Node::Scan(-1)],
+                EmitFormat::Text,
+                out
+            )),
+            "OutputBytes(\"a\\n\")\nScan(-1)\n"
+        );
+    }
+
+    #[test]
+    fn test_instructions() {
+        let program = Interpreter::compile(parse("+[->[-]<]."));
+        assert_eq!(
+            emit(|out| instructions(&program, EmitFormat::Text, out)),
+            "    0  Incr(1)\n\
+             \x20   1  BeginLoop -> 9\n\
+             \x20   2    Decr(1)\n\
+             \x20   3    Next(1)\n\
+             \x20   4    BeginLoop -> 7\n\
+             \x20   5      Decr(1)\n\
+             \x20   6    EndLoop -> 5\n\
+             \x20   7    Prev(1)\n\
+             \x20   8  EndLoop -> 2\n\
+             \x20   9  Output\n"
+        );
+
+        let program = [
+            Instruction::If(2),
+            Instruction::SetNext(0, 1),
+            Instruction::OutputBytes(b"a\n".to_vec()),
+            Instruction::Input,
+        ];
+        assert_eq!(
+            emit(|out| instructions(&program, EmitFormat::Text, out)),
+            "    0  If -> 3\n\
+             \x20   1    SetNext(0, 1)\n\
+             \x20   2    OutputBytes(\"a\\n\")\n\
+             \x20   3  Input\n"
+        );
+        assert_eq!(
+            emit(|out| instructions(&program[..3], EmitFormat::Json, out)),
+            "[{\"index\":0,\"instruction\":\"If\",\"operands\":[],\"target\":3},\
+             {\"index\":1,\"instruction\":\"SetNext\",\"operands\":[0,1]},\
+             {\"index\":2,\"instruction\":\"OutputBytes\",\"operands\":[97,10]}]\n"
+        );
+    }
+}
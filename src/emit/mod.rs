@@ -0,0 +1,3 @@
+mod emit;
+
+pub use self::emit::{ast, instructions, ir, json_string, tokens, EmitFormat, Stage};
@@ -0,0 +1,66 @@
+use super::interpreter::Instruction;
+
+/// How often every cell was read and written, and how far the pointer went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeHeatmap {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+    pub max_pointer: usize,
+}
+
+impl TapeHeatmap {
+    pub(super) fn new(tape_size: usize) -> Self {
+        TapeHeatmap {
+            reads: vec![0; tape_size],
+            writes: vec![0; tape_size],
+            max_pointer: 0,
+        }
+    }
+
+    /// Counts the cells `instruction` accessed, moving the pointer from
+    /// `before` to `after`
+    pub(super) fn record(&mut self, instruction: &Instruction, before: usize, after: usize) {
+        match instruction {
+            Instruction::Incr(_) | Instruction::Decr(_) => {
+                self.reads[before] += 1;
+                self.writes[before] += 1;
+            }
+            Instruction::Set(_) | Instruction::Input => self.writes[before] += 1,
+            Instruction::Output
+            | Instruction::BeginLoop(_)
+            | Instruction::EndLoop(_)
+            | Instruction::If(_) => self.reads[before] += 1,
+            Instruction::ScanRight | Instruction::ScanLeft => {
+                for reads in &mut self.reads[before.min(after)..=before.max(after)] {
+                    *reads += 1;
+                }
+            }
+            Instruction::Next(_) | Instruction::Prev(_) | Instruction::OutputBytes(_) => {}
+            Instruction::SetNext(..) | Instruction::SetPrev(..) => self.writes[before] += 1,
+            Instruction::NextEndLoop(..) | Instruction::PrevEndLoop(..) => self.reads[after] += 1,
+            Instruction::DecrEndLoop(..) => {
+                self.reads[before] += 1;
+                self.writes[before] += 1;
+            }
+            Instruction::NextScanRight(n) => {
+                for reads in &mut self.reads[before + n..=after] {
+                    *reads += 1;
+                }
+            }
+            Instruction::PrevScanLeft(n) => {
+                for reads in &mut self.reads[after..=before - n] {
+                    *reads += 1;
+                }
+            }
+        }
+        self.max_pointer = self.max_pointer.max(after);
+    }
+
+    /// Cells that were read or written at least once
+    pub fn used(&self) -> Option<(usize, usize)> {
+        let used = |index: &usize| self.reads[*index] > 0 || self.writes[*index] > 0;
+        let first = (0..self.reads.len()).find(used)?;
+        let last = (0..self.reads.len()).rev().find(used)?;
+        Some((first, last))
+    }
+}
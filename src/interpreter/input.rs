@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::interpreter::{CancelHandle, Error};
+
+/// How long a read on another thread is waited for before checking the
+/// cancel handle again
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Where `Instruction::Input` reads from
+pub enum Input<'a> {
+    /// Read on the interpreter's thread, which blocks until input arrives
+    Reader(Box<dyn Read + 'a>),
+    /// Read on a thread of its own, see `InterpreterBuilder::background_input`
+    Background(BackgroundInput),
+}
+
+impl Input<'_> {
+    /// Next byte, `None` at EOF. Waiting for a `Background` read stops at
+    /// `deadline` or once `cancel` is cancelled.
+    pub fn read_byte(
+        &mut self,
+        deadline: Option<Instant>,
+        cancel: &CancelHandle,
+    ) -> Result<Option<u8>, Error> {
+        match self {
+            Input::Reader(reader) => {
+                let mut buffer = [0; 1];
+                Ok(match reader.read(&mut buffer)? {
+                    0 => None,
+                    _ => Some(buffer[0]),
+                })
+            }
+            Input::Background(input) => input.read_byte(deadline, cancel),
+        }
+    }
+}
+
+/// Input read in chunks by a thread that starts on the first read. The
+/// thread is left blocked in `read` when the program ends before EOF.
+pub struct BackgroundInput {
+    reader: Option<Box<dyn Read + Send>>,
+    chunks: Option<Receiver<io::Result<Vec<u8>>>>,
+    buffer: VecDeque<u8>,
+}
+
+impl BackgroundInput {
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        BackgroundInput {
+            reader: Some(Box::new(reader)),
+            chunks: None,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    fn start(&mut self) {
+        if let Some(mut reader) = self.reader.take() {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || loop {
+                let mut chunk = vec![0; 4096];
+                let result = reader.read(&mut chunk).map(|len| {
+                    chunk.truncate(len);
+                    chunk
+                });
+                // an empty chunk or an error is the last thing sent
+                let done = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+                if sender.send(result).is_err() || done {
+                    break;
+                }
+            });
+            self.chunks = Some(receiver);
+        }
+    }
+
+    fn read_byte(
+        &mut self,
+        deadline: Option<Instant>,
+        cancel: &CancelHandle,
+    ) -> Result<Option<u8>, Error> {
+        loop {
+            if let Some(byte) = self.buffer.pop_front() {
+                return Ok(Some(byte));
+            }
+            self.start();
+            let Some(chunks) = &self.chunks else {
+                return Ok(None);
+            };
+
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => left.min(POLL_INTERVAL),
+                    _ => return Err(Error::TimedOut),
+                },
+                None => POLL_INTERVAL,
+            };
+
+            match chunks.recv_timeout(wait) {
+                Ok(Ok(chunk)) if !chunk.is_empty() => self.buffer.extend(chunk),
+                Ok(Err(err)) => {
+                    self.chunks = None;
+                    return Err(err.into());
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Disconnected) => self.chunks = None,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Never returns from `read`, like a terminal nobody types into
+    struct Blocked;
+
+    impl Read for Blocked {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_secs(3600));
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_read_byte() {
+        let mut input = Input::Background(BackgroundInput::new(&b"ab"[..]));
+        let cancel = CancelHandle::new();
+        assert_eq!(input.read_byte(None, &cancel).unwrap(), Some(b'a'));
+        assert_eq!(input.read_byte(None, &cancel).unwrap(), Some(b'b'));
+        assert_eq!(input.read_byte(None, &cancel).unwrap(), None);
+        assert_eq!(input.read_byte(None, &cancel).unwrap(), None);
+    }
+
+    #[test]
+    fn test_blocked_read() {
+        let mut input = Input::Background(BackgroundInput::new(Blocked));
+        let cancel = CancelHandle::new();
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(matches!(
+            input.read_byte(Some(deadline), &cancel),
+            Err(Error::TimedOut)
+        ));
+
+        cancel.cancel();
+        assert!(matches!(
+            input.read_byte(None, &cancel),
+            Err(Error::Cancelled)
+        ));
+    }
+}
@@ -1,16 +1,166 @@
-use std::io::{Read, Write};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufWriter, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::parser::ASTNode;
+use super::heatmap::TapeHeatmap;
+use super::input::{BackgroundInput, Input};
+use super::journal::{JournalEntry, JournalStart};
+use super::snapshot::{program_hash, Snapshot};
+use super::threaded::{Op, Threaded, MAX_OP_STEPS, NO_OP};
+use super::trace::{TraceEvent, Tracer};
+use super::watchpoint::{Access, Watchpoint};
+use crate::ir::{self, Node};
+use crate::lexer::Lexer;
+use crate::optimizer::{Optimizer, OptimizerOptions};
+use crate::parser::{ASTNode, ParseError, Parser};
+use crate::util::unlikely;
 
-pub struct Interpreter {
-    program: Vec<Instruction>,
+pub const DEFAULT_TAPE_SIZE: usize = 30000;
+
+/// Journal entries kept by default, older ones are dropped
+pub const DEFAULT_JOURNAL_LIMIT: usize = 1_000_000;
+
+/// How many instructions run between two checks of the timeout and cancellation flag
+const LIMIT_CHECK_INTERVAL: u64 = 1 << 16;
+
+pub struct Interpreter<'a> {
+    program: Rc<[Instruction]>,
     program_counter: usize,
     memory: Vec<u8>,
     memory_pointer: usize,
-    input: Box<dyn Read>,
-    output: Box<dyn Write>,
+    input: Input<'a>,
+    output: BufWriter<Box<dyn Write + 'a>>,
+    flush_policy: FlushPolicy,
+    steps: u64,
+    max_steps: u64,
+    next_check: u64,
+    pause_at: u64,
+    timeout: Option<Duration>,
+    cancel: CancelHandle,
+    pending_input: VecDeque<u8>,
+    input_closed: bool,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    next_watchpoint_id: usize,
+    watch_hits: Vec<usize>,
+    last_access: Option<Access>,
+    journal: Option<VecDeque<JournalEntry>>,
+    journal_limit: usize,
+    journal_start: Option<JournalStart>,
+    input_position: u64,
+    output_position: u64,
+    /// Bytes of the current `OutputBytes` already returned by `step_until_io`
+    output_bytes_sent: usize,
+    profile: Option<Vec<u64>>,
+    trace: Option<Tracer>,
+    heatmap: Option<TapeHeatmap>,
+    dispatch: Dispatch,
+    threaded: Threaded,
+}
+
+/// How `Interpreter::run` executes instructions. Both give the same results,
+/// `Match` is kept as a reference and for benchmarks.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Dispatch {
+    /// Match on every instruction in `program`
+    Match,
+    /// Run pre-decoded ops with absolute jumps and fused instruction pairs
+    #[default]
+    Threaded,
+}
+
+/// Why `Interpreter::step_until_io` handed control back to the host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The program wants to read but no input is pending, see `Interpreter::push_input`
+    NeedsInput,
+    /// The program wrote a byte
+    Output(u8),
+    /// The program ran to completion
+    Halted,
+    /// A single instruction was executed, only returned by `Interpreter::step`
+    Paused,
+}
+
+/// Stops a running interpreter from another thread, see `InterpreterBuilder::cancel_handle`.
+/// Cancellation is cooperative and noticed within a few thousand instructions.
+#[derive(Debug, Default, Clone)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
+/// Controls when buffered program output is handed to the underlying writer.
+/// Output is always flushed before reading input and when the program exits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FlushPolicy {
+    /// Flush only when the program exits (or blocks on input)
+    #[default]
+    Exit,
+    /// Flush after every `\n` written by the program
+    Newline,
+    /// Flush after every byte written by the program
+    Always,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Io(std::io::Error),
+    PointerOutOfBounds,
+    StepLimitExceeded,
+    TimedOut,
+    Cancelled,
+    /// The snapshot was taken from a different program
+    SnapshotMismatch,
+    /// The snapshot doesn't describe a reachable state of the program
+    InvalidSnapshot,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::PointerOutOfBounds => write!(f, "Memory pointer moved outside of the tape"),
+            Error::StepLimitExceeded => write!(f, "Step limit exceeded"),
+            Error::TimedOut => write!(f, "Timed out"),
+            Error::Cancelled => write!(f, "Cancelled"),
+            Error::SnapshotMismatch => write!(f, "Snapshot was taken from a different program"),
+            Error::InvalidSnapshot => write!(f, "Snapshot is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Incr(u8),
     Decr(u8),
@@ -23,115 +173,1089 @@ pub enum Instruction {
     ScanRight,
     ScanLeft,
     Set(u8),
+    // superinstructions made by `optimizer::peephole`, executed as one step
+    SetNext(u8, usize),
+    SetPrev(u8, usize),
+    NextEndLoop(usize, usize),
+    PrevEndLoop(usize, usize),
+    DecrEndLoop(u8, usize),
+    NextScanRight(usize),
+    PrevScanLeft(usize),
+    /// Output known when optimizing, see `optimizer::partial_eval`
+    OutputBytes(Vec<u8>),
+    /// Skips the next `offset` instructions if the current cell is zero, see
+    /// `ir::Node::If`
+    If(usize),
 }
 
-impl Interpreter {
-    pub fn new(data: Vec<ASTNode>) -> Self {
-        Interpreter {
-            memory: vec![0; 30000],
-            memory_pointer: 0,
-            program: Self::compile(data),
-            program_counter: 0,
-            input: Box::new(std::io::stdin()),
-            output: Box::new(std::io::stdout()),
+impl Instruction {
+    /// Jump offset of `EndLoop` and the superinstructions ending with one
+    pub fn end_loop(&self) -> Option<usize> {
+        match *self {
+            Instruction::EndLoop(offset)
+            | Instruction::NextEndLoop(_, offset)
+            | Instruction::PrevEndLoop(_, offset)
+            | Instruction::DecrEndLoop(_, offset) => Some(offset),
+            _ => None,
         }
     }
+}
 
-    #[allow(dead_code)]
-    pub fn new_with_instructions(program: Vec<Instruction>) -> Self {
+/// Prints the brainfuck the instruction was compiled from, or an equivalent.
+/// Constant output has no equivalent and is shown as a quoted string.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let repeat = |f: &mut fmt::Formatter<'_>, c: &str, n: usize| write!(f, "{}", c.repeat(n));
+        match *self {
+            Instruction::Incr(n) => repeat(f, "+", n as usize),
+            Instruction::Decr(n) => repeat(f, "-", n as usize),
+            Instruction::Next(n) => repeat(f, ">", n),
+            Instruction::Prev(n) => repeat(f, "<", n),
+            Instruction::Input => write!(f, ","),
+            Instruction::Output => write!(f, "."),
+            Instruction::BeginLoop(_) | Instruction::If(_) => write!(f, "["),
+            Instruction::EndLoop(_) => write!(f, "]"),
+            Instruction::ScanRight => write!(f, "[>]"),
+            Instruction::ScanLeft => write!(f, "[<]"),
+            Instruction::Set(n) => {
+                write!(f, "[-]")?;
+                repeat(f, "+", n as usize)
+            }
+            Instruction::SetNext(n, k) => write!(f, "{}{}", Instruction::Set(n), ">".repeat(k)),
+            Instruction::SetPrev(n, k) => write!(f, "{}{}", Instruction::Set(n), "<".repeat(k)),
+            Instruction::NextEndLoop(k, _) => write!(f, "{}]", ">".repeat(k)),
+            Instruction::PrevEndLoop(k, _) => write!(f, "{}]", "<".repeat(k)),
+            Instruction::DecrEndLoop(n, _) => write!(f, "{}]", "-".repeat(n as usize)),
+            Instruction::NextScanRight(k) => write!(f, "{}[>]", ">".repeat(k)),
+            Instruction::PrevScanLeft(k) => write!(f, "{}[<]", "<".repeat(k)),
+            Instruction::OutputBytes(ref bytes) => write!(f, "\"{}\"", bytes.escape_ascii()),
+        }
+    }
+}
+
+pub struct InterpreterBuilder<'a> {
+    input: Input<'a>,
+    output: Box<dyn Write + 'a>,
+    tape_size: usize,
+    flush_policy: FlushPolicy,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    cancel: CancelHandle,
+    dispatch: Dispatch,
+}
+
+impl<'a> InterpreterBuilder<'a> {
+    pub fn input(mut self, input: impl Read + 'a) -> Self {
+        self.input = Input::Reader(Box::new(input));
+        self
+    }
+
+    /// Reads `input` on a thread of its own, so the timeout and cancel handle
+    /// also stop a program that's waiting for input. The thread reads ahead
+    /// of the program.
+    pub fn background_input(mut self, input: impl Read + Send + 'static) -> Self {
+        self.input = Input::Background(BackgroundInput::new(input));
+        self
+    }
+
+    pub fn output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = tape_size;
+        self
+    }
+
+    pub fn flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    /// Stops `run` with `Error::StepLimitExceeded` after executing this many
+    /// instructions. Each instruction is one step, however much brainfuck it
+    /// stands for, so the same limit lets a more optimized program get further.
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Stops `run` with `Error::TimedOut` once it has been running for this
+    /// long. A program waiting for input only notices with `background_input`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops `run` with `Error::Cancelled` once `handle` is cancelled, which
+    /// like the timeout needs `background_input` to stop a waiting read
+    pub fn cancel_handle(mut self, handle: CancelHandle) -> Self {
+        self.cancel = handle;
+        self
+    }
+
+    pub fn dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    pub fn build(self, data: Vec<ASTNode>) -> Interpreter<'a> {
+        self.build_with_instructions(Interpreter::compile(data))
+    }
+
+    pub fn build_with_instructions(self, program: Vec<Instruction>) -> Interpreter<'a> {
         Interpreter {
-            memory: vec![0; 30000],
+            threaded: Threaded::new(&program),
+            dispatch: self.dispatch,
+            memory: vec![0; self.tape_size],
             memory_pointer: 0,
-            program,
+            program: program.into(),
             program_counter: 0,
-            input: Box::new(std::io::stdin()),
+            input: self.input,
+            output: BufWriter::new(self.output),
+            flush_policy: self.flush_policy,
+            steps: 0,
+            max_steps: self.max_steps.unwrap_or(u64::MAX),
+            next_check: 0,
+            pause_at: u64::MAX,
+            timeout: self.timeout,
+            cancel: self.cancel,
+            pending_input: VecDeque::new(),
+            input_closed: false,
+            watchpoints: Vec::new(),
+            next_watchpoint_id: 1,
+            watch_hits: Vec::new(),
+            last_access: None,
+            journal: None,
+            journal_limit: DEFAULT_JOURNAL_LIMIT,
+            journal_start: None,
+            input_position: 0,
+            output_position: 0,
+            output_bytes_sent: 0,
+            profile: None,
+            trace: None,
+            heatmap: None,
+        }
+    }
+}
+
+fn move_by(offset: isize) -> Instruction {
+    match offset {
+        ..=-1 => Instruction::Prev(offset.unsigned_abs()),
+        _ => Instruction::Next(offset as usize),
+    }
+}
+
+/// Parses, optimizes and runs `program` in-process, feeding it `input` and
+/// returning everything it wrote.
+pub fn run_bytes(program: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut parser = Parser::new(Lexer::new(program.to_string()));
+    let ast = parser.try_parse()?;
+
+    let options = OptimizerOptions::default();
+    let mut optimizer = Optimizer::new(&ast.data);
+    optimizer.optimize_with(&options);
+
+    let mut output = Vec::new();
+    Interpreter::builder()
+        .input(input)
+        .output(&mut output)
+        .build_with_instructions(optimizer.compile(&options))
+        .run()?;
+
+    Ok(output)
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn builder() -> InterpreterBuilder<'a> {
+        InterpreterBuilder {
+            input: Input::Reader(Box::new(std::io::stdin())),
             output: Box::new(std::io::stdout()),
+            tape_size: DEFAULT_TAPE_SIZE,
+            flush_policy: FlushPolicy::default(),
+            max_steps: None,
+            timeout: None,
+            cancel: CancelHandle::new(),
+            dispatch: Dispatch::default(),
         }
     }
 
-    fn compile(ast: Vec<ASTNode>) -> Vec<Instruction> {
+    pub fn new(data: Vec<ASTNode>) -> Self {
+        Self::builder().build(data)
+    }
+
+    pub fn new_with_instructions(program: Vec<Instruction>) -> Self {
+        Self::builder().build_with_instructions(program)
+    }
+
+    pub fn compile(ast: Vec<ASTNode>) -> Vec<Instruction> {
+        Self::compile_ir(&ir::lower(&ast))
+    }
+
+    /// Lowers IR to instructions, one per node unless it addresses a cell
+    /// away from the pointer
+    pub fn compile_ir(nodes: &[Node]) -> Vec<Instruction> {
         let mut program = Vec::new();
 
-        for node in ast {
+        for node in nodes {
+            let offset = node.offset();
+            if offset != 0 {
+                program.push(move_by(offset));
+            }
+
             match node {
-                ASTNode::Incr(data) => program.push(Instruction::Incr(data)),
-                ASTNode::Decr(data) => program.push(Instruction::Decr(data)),
-                ASTNode::Next(data) => program.push(Instruction::Next(data)),
-                ASTNode::Prev(data) => program.push(Instruction::Prev(data)),
-                ASTNode::Input => program.push(Instruction::Input),
-                ASTNode::Output => program.push(Instruction::Output),
-                ASTNode::Loop(loop_program) => {
-                    let loop_program = Self::compile(loop_program);
+                Node::Add { delta, .. } if *delta < 0 => {
+                    program.push(Instruction::Decr(delta.unsigned_abs()))
+                }
+                Node::Add { delta, .. } => program.push(Instruction::Incr(*delta as u8)),
+                Node::Set { value, .. } => program.push(Instruction::Set(*value)),
+                Node::Move(offset) => program.push(move_by(*offset)),
+                Node::Input { .. } => program.push(Instruction::Input),
+                Node::Output { .. } => program.push(Instruction::Output),
+                Node::OutputBytes(bytes) => program.push(Instruction::OutputBytes(bytes.clone())),
+                Node::Scan(step) if *step < 0 => program.push(Instruction::ScanLeft),
+                Node::Scan(_) => program.push(Instruction::ScanRight),
+                Node::Loop(inner) => {
+                    let loop_program = Self::compile_ir(&inner.body);
                     let offset = loop_program.len() + 1;
                     program.push(Instruction::BeginLoop(offset));
                     program.extend(loop_program);
                     program.push(Instruction::EndLoop(offset));
                 }
-                ASTNode::Set(data) => program.push(Instruction::Set(data)),
-                ASTNode::ScanLeft => program.push(Instruction::ScanLeft),
-                ASTNode::ScanRight => program.push(Instruction::ScanRight),
-                ASTNode::NoOp => {}
-                ASTNode::Comment(_) => {}
+                Node::If(body) => {
+                    let body = Self::compile_ir(body);
+                    program.push(Instruction::If(body.len()));
+                    program.extend(body);
+                }
+            }
+
+            if offset != 0 {
+                program.push(move_by(-offset));
             }
         }
 
         program
     }
 
-    pub fn run(&mut self) {
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Number of instructions executed so far, see `InterpreterBuilder::max_steps`
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_pointer(&self) -> usize {
+        self.memory_pointer
+    }
+
+    /// Runs the program to completion, flushing any buffered output even when
+    /// execution stops with an error
+    pub fn run(&mut self) -> Result<(), Error> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.next_check = self.steps;
+
+        let result = self.dispatch(deadline, false);
+        let flushed = self.output.flush();
+
+        result?;
+        Ok(flushed?)
+    }
+
+    /// Runs the program until it reads or writes, without touching the
+    /// configured input and output. Reads are served from bytes given to
+    /// `push_input`; when none are left `Event::NeedsInput` is returned and the
+    /// read is retried on the next call. The timeout applies to each call.
+    pub fn step_until_io(&mut self) -> Result<Event, Error> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.next_check = self.steps;
+
+        self.dispatch(deadline, true)
+    }
+
+    /// Executes a single instruction, returning `Event::Paused` unless it
+    /// produced another event. I/O is handled like in `step_until_io`.
+    pub fn step(&mut self) -> Result<Event, Error> {
+        let program_counter = self.program_counter;
+        let pointer = self.memory_pointer;
+        let value = self.memory.get(pointer).copied().unwrap_or(0);
+        let steps = self.steps;
+
+        self.pause_at = self.steps + 1;
+        self.next_check = self.steps;
+
+        let result = self.dispatch(None, true);
+        self.pause_at = u64::MAX;
+
+        self.last_access = None;
+        self.watch_hits.clear();
+        if self.steps > steps {
+            let access = self.access(program_counter, pointer, value);
+            self.watch_hits.extend(
+                self.watchpoints
+                    .iter()
+                    .filter(|(_, watchpoint)| watchpoint.triggered_by(&access))
+                    .map(|(id, _)| *id),
+            );
+            self.last_access = Some(access);
+        }
+
+        result
+    }
+
+    /// Journals the instruction `execute` started last, unless it was taken
+    /// back to run again
+    fn finish_journal_entry(&mut self) {
+        let Some(start) = self.journal_start.take() else {
+            return;
+        };
+        if self.steps < start.steps {
+            return;
+        }
+
+        let access = self.access(start.program_counter, start.pointer, start.value);
+        let consumed = self.pending_input.len() < start.pending_input;
+        if let Some(journal) = &mut self.journal {
+            if journal.len() == self.journal_limit {
+                journal.pop_front();
+            }
+            journal.push_back(JournalEntry {
+                access,
+                input: access.write.filter(|_| consumed).map(|(_, _, new)| new),
+            });
+        }
+    }
+
+    /// Starts or stops recording every executed instruction, so they can be
+    /// undone with `step_back`. Stopping drops the recorded journal. Only the
+    /// last `set_journal_limit` instructions are kept.
+    pub fn set_journaling(&mut self, enabled: bool) {
+        self.journal = enabled.then(VecDeque::new);
+    }
+
+    /// Keeps at most `limit` journal entries, dropping the oldest ones. A
+    /// limit of 0 keeps none, which turns journaling off.
+    pub fn set_journal_limit(&mut self, limit: usize) {
+        self.journal_limit = limit;
+        if let Some(journal) = &mut self.journal {
+            let excess = journal.len().saturating_sub(self.journal_limit);
+            journal.drain(..excess);
+        }
+    }
+
+    /// Number of instructions `step_back` can undo
+    pub fn journal_len(&self) -> usize {
+        self.journal.as_ref().map_or(0, VecDeque::len)
+    }
+
+    /// Undoes the last journaled instruction, restoring the tape, pointers and
+    /// any input it took from `push_input`. Output already written and input
+    /// read from the configured reader can't be taken back.
+    pub fn step_back(&mut self) -> Option<JournalEntry> {
+        let entry = self.journal.as_mut()?.pop_back()?;
+        let access = entry.access;
+
+        if let Some((cell, old, _)) = access.write {
+            self.memory[cell] = old;
+        }
+        if let Some(byte) = entry.input {
+            self.pending_input.push_front(byte);
+            self.input_position -= 1;
+        }
+        self.memory_pointer = access.pointer.0;
+        self.program_counter = access.program_counter;
+        self.output_bytes_sent = 0;
+        self.steps -= 1;
+        if let Some(profile) = &mut self.profile {
+            profile[access.program_counter] -= 1;
+        }
+
+        self.last_access = None;
+        self.watch_hits.clear();
+        Some(entry)
+    }
+
+    /// Works out what the instruction at `program_counter` did, given the
+    /// pointer and current cell before it ran
+    fn access(&self, program_counter: usize, pointer: usize, value: u8) -> Access {
+        let after = self.memory_pointer;
+        let written = Some((pointer, value, self.memory[pointer]));
+
+        let (read, write) = match self.program[program_counter] {
+            Instruction::Incr(_) | Instruction::Decr(_) => (Some((pointer, pointer)), written),
+            Instruction::Set(_) | Instruction::Input => (None, written),
+            Instruction::Output
+            | Instruction::BeginLoop(_)
+            | Instruction::EndLoop(_)
+            | Instruction::If(_) => (Some((pointer, pointer)), None),
+            Instruction::ScanRight | Instruction::ScanLeft => {
+                (Some((pointer.min(after), pointer.max(after))), None)
+            }
+            Instruction::Next(_) | Instruction::Prev(_) => (None, None),
+            Instruction::SetNext(..) | Instruction::SetPrev(..) => (None, written),
+            Instruction::NextEndLoop(..) | Instruction::PrevEndLoop(..) => {
+                (Some((after, after)), None)
+            }
+            Instruction::DecrEndLoop(..) => (Some((pointer, pointer)), written),
+            Instruction::NextScanRight(k) => {
+                let start = pointer + k;
+                (Some((start.min(after), start.max(after))), None)
+            }
+            Instruction::PrevScanLeft(k) => {
+                let start = pointer.saturating_sub(k);
+                (Some((start.min(after), start.max(after))), None)
+            }
+            Instruction::OutputBytes(_) => (None, None),
+        };
+
+        Access {
+            program_counter,
+            read,
+            write,
+            pointer: (pointer, after),
+        }
+    }
+
+    /// Captures everything needed to continue execution later, see `restore`
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program_hash: program_hash(&self.program),
+            program_counter: self.program_counter,
+            memory_pointer: self.memory_pointer,
+            memory: self.memory.clone(),
+            steps: self.steps,
+            input_position: self.input_position,
+            output_position: self.output_position,
+            pending_input: self.pending_input.iter().copied().collect(),
+        }
+    }
+
+    /// Continues from a snapshot of the same program. The tape takes the size
+    /// it had when the snapshot was taken and the journal is cleared.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        if snapshot.program_hash != program_hash(&self.program) {
+            return Err(Error::SnapshotMismatch);
+        }
+        if snapshot.program_counter > self.program.len()
+            || snapshot.memory_pointer >= snapshot.memory.len()
+        {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        self.program_counter = snapshot.program_counter;
+        self.memory_pointer = snapshot.memory_pointer;
+        self.memory.clone_from(&snapshot.memory);
+        self.steps = snapshot.steps;
+        self.input_position = snapshot.input_position;
+        self.output_position = snapshot.output_position;
+        self.pending_input = snapshot.pending_input.iter().copied().collect();
+
+        self.last_access = None;
+        self.output_bytes_sent = 0;
+        self.watch_hits.clear();
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+        if let Some(heatmap) = &mut self.heatmap {
+            *heatmap = TapeHeatmap::new(self.memory.len());
+        }
+        Ok(())
+    }
+
+    /// Starts or stops counting how often each instruction is executed.
+    /// Stopping drops the counts.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(|| vec![0; self.program.len()]);
+    }
+
+    /// Execution count of every instruction, indexed like `program`
+    pub fn profile(&self) -> Option<&[u64]> {
+        self.profile.as_deref()
+    }
+
+    /// Starts recording every run of a loop that takes at least `min_steps`
+    /// instructions, see `take_trace`
+    pub fn set_tracing(&mut self, min_steps: Option<u64>) {
+        self.trace = min_steps.map(Tracer::new);
+    }
+
+    /// Loop runs recorded so far, in the order they ended. Loops that are
+    /// still running end at the current step.
+    pub fn take_trace(&mut self) -> Vec<TraceEvent> {
+        match &mut self.trace {
+            Some(trace) => trace.finish(self.steps),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts or stops counting reads and writes of every cell
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.heatmap = enabled.then(|| TapeHeatmap::new(self.memory.len()));
+    }
+
+    pub fn heatmap(&self) -> Option<&TapeHeatmap> {
+        self.heatmap.as_ref()
+    }
+
+    /// Bytes read and written by the program so far
+    pub fn io_positions(&self) -> (u64, u64) {
+        (self.input_position, self.output_position)
+    }
+
+    /// Watchpoints are only checked by `step`, returns an id for `remove_watchpoint`
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.next_watchpoint_id;
+        self.next_watchpoint_id += 1;
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|(watchpoint_id, _)| *watchpoint_id != id);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[(usize, Watchpoint)] {
+        &self.watchpoints
+    }
+
+    /// Ids of the watchpoints triggered by the last `step`
+    pub fn watch_hits(&self) -> &[usize] {
+        &self.watch_hits
+    }
+
+    /// What the last `step` did to the tape, if it executed an instruction
+    pub fn last_access(&self) -> Option<&Access> {
+        self.last_access.as_ref()
+    }
+
+    /// Queues input for programs driven by `step_until_io`
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.pending_input.extend(input);
+    }
+
+    /// Signals that no more input will be pushed, pending reads see EOF (zero)
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Returns whether execution should pause for `step`
+    fn check_limits(&mut self, deadline: Option<Instant>) -> Result<bool, Error> {
+        if self.steps >= self.max_steps {
+            return Err(Error::StepLimitExceeded);
+        }
+        if self.steps >= self.pause_at {
+            return Ok(true);
+        }
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::TimedOut);
+        }
+
+        self.next_check = self
+            .steps
+            .saturating_add(LIMIT_CHECK_INTERVAL)
+            .min(self.max_steps)
+            .min(self.pause_at);
+        Ok(false)
+    }
+
+    /// Keeps profiling and tracing out of the main loop unless they're enabled
+    fn dispatch(&mut self, deadline: Option<Instant>, yield_io: bool) -> Result<Event, Error> {
+        let journaling = self.journal.is_some() && self.journal_limit > 0;
+        if self.profile.is_some() || self.trace.is_some() || self.heatmap.is_some() || journaling
+        {
+            let result = self.execute::<true>(deadline, yield_io);
+            self.finish_journal_entry();
+            return result;
+        }
+        match (self.dispatch, yield_io || self.pause_at != u64::MAX) {
+            (Dispatch::Threaded, false) => self.execute_threaded(deadline),
+            _ => self.execute::<false>(deadline, yield_io),
+        }
+    }
+
+    /// Runs ops while it's safe to, falling back to single instructions where
+    /// ops can't start or when a limit is close
+    fn execute_threaded(&mut self, deadline: Option<Instant>) -> Result<Event, Error> {
         while self.program_counter < self.program.len() {
-            if let Some(instr) = self.program.get(self.program_counter) {
-                (*self).program_counter += 1;
+            if unlikely(self.steps >= self.next_check) && self.check_limits(deadline)? {
+                return Ok(Event::Paused);
+            }
+
+            let op = self.threaded.op_at[self.program_counter];
+            let until = self.next_check.saturating_sub(MAX_OP_STEPS);
+            if op != NO_OP && self.steps < until {
+                self.run_ops(op, until, deadline)?;
+                continue;
+            }
+
+            self.pause_at = self.steps + 1;
+            self.next_check = self.steps;
+            let result = self.execute::<false>(deadline, false);
+            self.pause_at = u64::MAX;
+            if !matches!(result?, Event::Paused) {
+                break;
+            }
+        }
+
+        Ok(Event::Halted)
+    }
+
+    /// Runs ops from `index` until the program ends or `until` steps have been
+    /// executed, leaving the same state as running the instructions one by one
+    fn run_ops(
+        &mut self,
+        mut index: usize,
+        until: u64,
+        deadline: Option<Instant>,
+    ) -> Result<(), Error> {
+        let ops = &self.threaded.ops;
+        let pcs = &self.threaded.pcs;
+        let memory = &mut self.memory[..];
+        let mut pointer = self.memory_pointer;
+        let mut steps = self.steps;
+
+        // errors always come from the last instruction of an op, so the program
+        // counter ends up at the start of the next op either way
+        let result = loop {
+            if steps >= until {
+                break Ok(());
+            }
+            let Some(&op) = ops.get(index) else {
+                break Ok(());
+            };
+            index += 1;
+            steps += 1;
+
+            match op {
+                Op::Add(delta) => memory[pointer] = memory[pointer].wrapping_add(delta),
+                Op::Move(offset) => {
+                    if !shift(&mut pointer, offset, memory.len()) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::AddMove(delta, offset) => {
+                    steps += 1;
+                    memory[pointer] = memory[pointer].wrapping_add(delta);
+                    if !shift(&mut pointer, offset, memory.len()) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::Set(value) => memory[pointer] = value,
+                Op::JumpIfZero(target) => {
+                    if memory[pointer] == 0 {
+                        index = target;
+                    }
+                }
+                Op::JumpIfNotZero(target) => {
+                    if memory[pointer] != 0 {
+                        index = target;
+                    }
+                }
+                Op::ScanRight => {
+                    if !scan_right(memory, &mut pointer) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::ScanLeft => {
+                    if !scan_left(memory, &mut pointer) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::Input => {
+                    // make sure prompts are visible before blocking on input
+                    if let Err(err) = self.output.flush() {
+                        break Err(err.into());
+                    }
+
+                    // EOF leaves a zero in the current cell
+                    memory[pointer] = match self.input.read_byte(deadline, &self.cancel) {
+                        Ok(None) => 0,
+                        Ok(Some(byte)) => {
+                            self.input_position += 1;
+                            byte
+                        }
+                        Err(err) => break Err(err),
+                    };
+                }
+                Op::Output => {
+                    let byte = memory[pointer];
+                    if let Err(err) = self.output.write_all(&[byte]) {
+                        break Err(err.into());
+                    }
+                    self.output_position += 1;
+
+                    let flushed = match self.flush_policy {
+                        FlushPolicy::Always => self.output.flush(),
+                        FlushPolicy::Newline if byte == b'\n' => self.output.flush(),
+                        _ => Ok(()),
+                    };
+                    if let Err(err) = flushed {
+                        break Err(err.into());
+                    }
+                }
+                Op::OutputBytes(start, end) => {
+                    let bytes = &self.threaded.data[start..end];
+                    if let Err(err) = write_bytes(&mut self.output, self.flush_policy, bytes) {
+                        break Err(err.into());
+                    }
+                    self.output_position += bytes.len() as u64;
+                }
+                Op::SetMove(value, offset) => {
+                    memory[pointer] = value;
+                    if !shift(&mut pointer, offset, memory.len()) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::MoveJumpIfNotZero(offset, target) => {
+                    if !shift(&mut pointer, offset, memory.len()) {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                    if memory[pointer] != 0 {
+                        index = target;
+                    }
+                }
+                Op::AddJumpIfNotZero(delta, target) => {
+                    memory[pointer] = memory[pointer].wrapping_add(delta);
+                    if memory[pointer] != 0 {
+                        index = target;
+                    }
+                }
+                Op::MoveScanRight(offset) => {
+                    if !shift(&mut pointer, offset as isize, memory.len())
+                        || !scan_right(memory, &mut pointer)
+                    {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+                Op::MoveScanLeft(offset) => {
+                    if !shift(&mut pointer, -(offset as isize), memory.len())
+                        || !scan_left(memory, &mut pointer)
+                    {
+                        break Err(Error::PointerOutOfBounds);
+                    }
+                }
+            }
+        };
+
+        self.memory_pointer = pointer;
+        self.steps = steps;
+        self.program_counter = pcs[index];
+        result
+    }
+
+    #[inline(always)]
+    fn next(&mut self, offset: usize) -> Result<(), Error> {
+        self.memory_pointer += offset;
+        if unlikely(self.memory_pointer >= self.memory.len()) {
+            return Err(Error::PointerOutOfBounds);
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn prev(&mut self, offset: usize) -> Result<(), Error> {
+        self.memory_pointer = match self.memory_pointer.checked_sub(offset) {
+            Some(pointer) => pointer,
+            None => return Err(Error::PointerOutOfBounds),
+        };
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn scan_right(&mut self) -> Result<(), Error> {
+        while self.memory[self.memory_pointer] != 0 {
+            self.next(1)?;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn scan_left(&mut self) -> Result<(), Error> {
+        while self.memory[self.memory_pointer] != 0 {
+            self.prev(1)?;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn end_loop<const INSTRUMENTED: bool>(&mut self, offset: usize) {
+        if self.memory[self.memory_pointer] != 0 {
+            self.program_counter -= offset;
+        } else if INSTRUMENTED {
+            if let Some(trace) = &mut self.trace {
+                trace.leave(self.steps);
+            }
+        }
+    }
+
+    fn execute<const INSTRUMENTED: bool>(
+        &mut self,
+        deadline: Option<Instant>,
+        yield_io: bool,
+    ) -> Result<Event, Error> {
+        // a handle of its own keeps the current instruction borrowable while
+        // the interpreter changes
+        let program = Rc::clone(&self.program);
+        while self.program_counter < program.len() {
+            if unlikely(self.steps >= self.next_check) && self.check_limits(deadline)? {
+                return Ok(Event::Paused);
+            }
+            self.steps += 1;
+            let pointer = self.memory_pointer;
+            if INSTRUMENTED {
+                if let Some(profile) = &mut self.profile {
+                    profile[self.program_counter] += 1;
+                }
+                if self.journal.is_some() && self.journal_limit > 0 {
+                    self.finish_journal_entry();
+                    self.journal_start = Some(JournalStart {
+                        program_counter: self.program_counter,
+                        pointer,
+                        value: self.memory.get(pointer).copied().unwrap_or(0),
+                        pending_input: self.pending_input.len(),
+                        steps: self.steps,
+                    });
+                }
+            }
+
+            if let Some(instr) = program.get(self.program_counter) {
+                self.program_counter += 1;
 
                 match instr {
                     Instruction::Incr(data) => {
-                        self.memory[self.memory_pointer] += data;
+                        let cell = &mut self.memory[self.memory_pointer];
+                        *cell = cell.wrapping_add(*data);
                     }
                     Instruction::Decr(data) => {
-                        self.memory[self.memory_pointer] -= data;
+                        let cell = &mut self.memory[self.memory_pointer];
+                        *cell = cell.wrapping_sub(*data);
                     }
-                    Instruction::Next(data) => {
-                        self.memory_pointer += *data;
+                    Instruction::Next(data) => self.next(*data)?,
+                    Instruction::Prev(data) => self.prev(*data)?,
+                    Instruction::Input if yield_io => {
+                        self.memory[self.memory_pointer] = match self.pending_input.pop_front() {
+                            Some(byte) => {
+                                self.input_position += 1;
+                                byte
+                            }
+                            None if self.input_closed => 0,
+                            None => {
+                                // retry the read once the host has pushed more input
+                                self.program_counter -= 1;
+                                self.steps -= 1;
+                                if INSTRUMENTED {
+                                    if let Some(profile) = &mut self.profile {
+                                        profile[self.program_counter] -= 1;
+                                    }
+                                }
+                                return Ok(Event::NeedsInput);
+                            }
+                        };
                     }
-                    Instruction::Prev(data) => {
-                        self.memory_pointer -= *data;
+                    Instruction::Output if yield_io => {
+                        if INSTRUMENTED {
+                            if let Some(heatmap) = &mut self.heatmap {
+                                heatmap.record(instr, pointer, pointer);
+                            }
+                        }
+                        self.output_position += 1;
+                        return Ok(Event::Output(self.memory[self.memory_pointer]));
                     }
                     Instruction::Input => {
-                        let mut buffer = [0; 1];
-                        self.input.read(&mut buffer).unwrap();
-                        self.memory[self.memory_pointer] = buffer[0];
+                        // make sure prompts are visible before blocking on input
+                        self.output.flush()?;
+
+                        // EOF leaves a zero in the current cell
+                        let byte = self.input.read_byte(deadline, &self.cancel)?;
+                        self.memory[self.memory_pointer] = match byte {
+                            None => 0,
+                            Some(byte) => {
+                                self.input_position += 1;
+                                byte
+                            }
+                        };
+                    }
+                    Instruction::OutputBytes(bytes) if yield_io => {
+                        let byte = bytes[self.output_bytes_sent];
+                        self.output_bytes_sent += 1;
+                        self.output_position += 1;
+                        if self.output_bytes_sent < bytes.len() {
+                            // the instruction runs again for the next byte
+                            self.program_counter -= 1;
+                            self.steps -= 1;
+                            if INSTRUMENTED {
+                                if let Some(profile) = &mut self.profile {
+                                    profile[self.program_counter] -= 1;
+                                }
+                            }
+                        } else {
+                            self.output_bytes_sent = 0;
+                        }
+                        return Ok(Event::Output(byte));
+                    }
+                    Instruction::OutputBytes(bytes) => {
+                        write_bytes(&mut self.output, self.flush_policy, bytes)?;
+                        self.output_position += bytes.len() as u64;
                     }
                     Instruction::Output => {
-                        self.output
-                            .write(&[self.memory[self.memory_pointer]])
-                            .unwrap();
+                        let byte = self.memory[self.memory_pointer];
+                        self.output.write_all(&[byte])?;
+                        self.output_position += 1;
+
+                        match self.flush_policy {
+                            FlushPolicy::Always => self.output.flush()?,
+                            FlushPolicy::Newline if byte == b'\n' => self.output.flush()?,
+                            _ => {}
+                        }
                     }
                     Instruction::BeginLoop(offset) => {
                         if self.memory[self.memory_pointer] == 0 {
                             self.program_counter += offset;
+                        } else if INSTRUMENTED {
+                            if let Some(trace) = &mut self.trace {
+                                trace.enter(self.program_counter - 1, self.steps - 1);
+                            }
                         }
                     }
-                    Instruction::EndLoop(offset) => {
-                        if self.memory[self.memory_pointer] != 0 {
-                            self.program_counter -= offset;
+                    Instruction::EndLoop(offset) => self.end_loop::<INSTRUMENTED>(*offset),
+                    Instruction::If(offset) => {
+                        if self.memory[self.memory_pointer] == 0 {
+                            self.program_counter += offset;
                         }
                     }
                     Instruction::Set(data) => {
                         self.memory[self.memory_pointer] = *data;
                     }
-                    Instruction::ScanRight => {
-                        while self.memory[self.memory_pointer] != 0 {
-                            self.memory_pointer += 1;
-                        }
+                    Instruction::ScanRight => self.scan_right()?,
+                    Instruction::ScanLeft => self.scan_left()?,
+                    Instruction::SetNext(data, offset) => {
+                        self.memory[self.memory_pointer] = *data;
+                        self.next(*offset)?;
                     }
-                    Instruction::ScanLeft => {
-                        while self.memory[self.memory_pointer] != 0 {
-                            self.memory_pointer -= 1;
-                        }
+                    Instruction::SetPrev(data, offset) => {
+                        self.memory[self.memory_pointer] = *data;
+                        self.prev(*offset)?;
+                    }
+                    Instruction::NextEndLoop(data, offset) => {
+                        self.next(*data)?;
+                        self.end_loop::<INSTRUMENTED>(*offset);
+                    }
+                    Instruction::PrevEndLoop(data, offset) => {
+                        self.prev(*data)?;
+                        self.end_loop::<INSTRUMENTED>(*offset);
+                    }
+                    Instruction::DecrEndLoop(data, offset) => {
+                        let cell = &mut self.memory[self.memory_pointer];
+                        *cell = cell.wrapping_sub(*data);
+                        self.end_loop::<INSTRUMENTED>(*offset);
+                    }
+                    Instruction::NextScanRight(data) => {
+                        self.next(*data)?;
+                        self.scan_right()?;
+                    }
+                    Instruction::PrevScanLeft(data) => {
+                        self.prev(*data)?;
+                        self.scan_left()?;
+                    }
+                }
+
+                if INSTRUMENTED {
+                    if let Some(heatmap) = &mut self.heatmap {
+                        heatmap.record(instr, pointer, self.memory_pointer);
                     }
                 }
             }
         }
+
+        Ok(Event::Halted)
+    }
+}
+
+/// Writes `bytes` as if they were output one at a time
+fn write_bytes(
+    output: &mut impl Write,
+    flush_policy: FlushPolicy,
+    bytes: &[u8],
+) -> std::io::Result<()> {
+    match flush_policy {
+        FlushPolicy::Exit => output.write_all(bytes),
+        FlushPolicy::Newline => {
+            for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+                output.write_all(line)?;
+                if line.ends_with(b"\n") {
+                    output.flush()?;
+                }
+            }
+            Ok(())
+        }
+        FlushPolicy::Always => {
+            for byte in bytes {
+                output.write_all(&[*byte])?;
+                output.flush()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Moves `pointer` by `offset` like `Next` and `Prev` do, returning whether
+/// it's still on the tape
+#[inline(always)]
+fn shift(pointer: &mut usize, offset: isize, len: usize) -> bool {
+    let next = pointer.wrapping_add_signed(offset);
+    if unlikely(next >= len) {
+        // `Prev` stays put when it would go below zero, `Next` doesn't
+        if offset > 0 {
+            *pointer = next;
+        }
+        return false;
+    }
+    *pointer = next;
+    true
+}
+
+/// `ScanRight` on a slice, leaving `pointer` past the end if there's no zero
+#[inline(always)]
+fn scan_right(memory: &[u8], pointer: &mut usize) -> bool {
+    match memory[*pointer..].iter().position(|&cell| cell == 0) {
+        Some(distance) => *pointer += distance,
+        None => *pointer = memory.len(),
+    }
+    *pointer < memory.len()
+}
+
+/// `ScanLeft` on a slice, leaving `pointer` at zero if there's no zero
+#[inline(always)]
+fn scan_left(memory: &[u8], pointer: &mut usize) -> bool {
+    match memory[..=*pointer].iter().rposition(|&cell| cell == 0) {
+        Some(cell) => {
+            *pointer = cell;
+            true
+        }
+        None => {
+            *pointer = 0;
+            false
+        }
     }
 }
 
@@ -171,19 +1295,646 @@ mod tests {
 
     #[test]
     fn test_interpreter() {
-        let mut interpreter = Interpreter::new_with_instructions(vec![
-            Instruction::Incr(1),
-            Instruction::Output,
-            Instruction::Next(2),
-            Instruction::Set(42),
-            Instruction::ScanLeft,
-            Instruction::Set(32),
-        ]);
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::builder()
+            .output(&mut output)
+            .build_with_instructions(vec![
+                Instruction::Incr(1),
+                Instruction::Output,
+                Instruction::Next(2),
+                Instruction::Set(42),
+                Instruction::ScanLeft,
+                Instruction::Set(32),
+            ]);
 
-        interpreter.run();
+        interpreter.run().unwrap();
 
         assert_eq!(interpreter.memory[0], 1);
         assert_eq!(interpreter.memory[2], 42);
         assert_eq!(interpreter.memory[1], 32);
+
+        drop(interpreter);
+        assert_eq!(output, vec![1]);
+    }
+
+    #[test]
+    fn test_compile_ir() {
+        let program = Interpreter::compile_ir(&[
+            Node::Add {
+                offset: 2,
+                delta: -1,
+            },
+            Node::Loop(ir::Loop::new(vec![Node::Scan(-1)])),
+            Node::Output { offset: 0 },
+        ]);
+
+        assert_eq!(
+            program,
+            [
+                Instruction::Next(2),
+                Instruction::Decr(1),
+                Instruction::Prev(2),
+                Instruction::BeginLoop(2),
+                Instruction::ScanLeft,
+                Instruction::EndLoop(2),
+                Instruction::Output,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let interpreter = Interpreter::builder()
+            .tape_size(16)
+            .flush_policy(FlushPolicy::Always)
+            .build(vec![]);
+
+        assert_eq!(interpreter.memory.len(), 16);
+        assert_eq!(interpreter.flush_policy, FlushPolicy::Always);
+    }
+
+    #[test]
+    fn test_run_bytes() {
+        let hello = std::fs::read_to_string("examples/hello.b").unwrap();
+        assert_eq!(run_bytes(&hello, b"").unwrap(), b"Hello World!\n");
+
+        assert_eq!(run_bytes(",[.,]", b"echo").unwrap(), b"echo");
+        assert_eq!(run_bytes(",+.", b"").unwrap(), vec![1]);
+        assert_eq!(run_bytes("-.", b"").unwrap(), vec![255]);
+    }
+
+    #[test]
+    fn test_run_bytes_errors() {
+        assert!(matches!(
+            run_bytes("[", b""),
+            Err(Error::Parse(ParseError::UnmatchedLoopStart))
+        ));
+        assert!(matches!(
+            run_bytes("<", b""),
+            Err(Error::PointerOutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn test_tape_size() {
+        let mut parser = Parser::new(Lexer::new(String::from(">>>+")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::builder().tape_size(3).build(ast.data);
+
+        assert!(matches!(interpreter.run(), Err(Error::PointerOutOfBounds)));
+    }
+
+    fn limited(program: &str, builder: InterpreterBuilder) -> (Result<(), Error>, Vec<u8>) {
+        let mut output = Vec::new();
+        let mut parser = Parser::new(Lexer::new(String::from(program)));
+        let ast = parser.parse_helper();
+
+        let result = builder.output(&mut output).build(ast.data).run();
+        (result, output)
+    }
+
+    #[test]
+    fn test_max_steps() {
+        let (result, output) = limited("+.[]", Interpreter::builder().max_steps(1000));
+        assert!(matches!(result, Err(Error::StepLimitExceeded)));
+        assert_eq!(output, vec![1]);
+
+        // a program finishing exactly at the limit is fine
+        let (result, _) = limited("+++", Interpreter::builder().max_steps(1));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_max_steps_exact() {
+        let mut parser = Parser::new(Lexer::new(String::from("+>+>+")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::builder().max_steps(3).build(ast.data);
+
+        assert!(matches!(interpreter.run(), Err(Error::StepLimitExceeded)));
+        assert_eq!(interpreter.steps(), 3);
+        assert_eq!(interpreter.memory[..3], [1, 1, 0]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (result, output) = limited(
+            "+.[]",
+            Interpreter::builder().timeout(Duration::from_millis(10)),
+        );
+        assert!(matches!(result, Err(Error::TimedOut)));
+        assert_eq!(output, vec![1]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let handle = CancelHandle::new();
+        let canceller = {
+            let handle = handle.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                handle.cancel();
+            })
+        };
+
+        let (result, _) = limited("+[]", Interpreter::builder().cancel_handle(handle));
+        canceller.join().unwrap();
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_timeout_while_reading() {
+        /// Never returns from `read`, like a terminal nobody types into
+        struct Blocked;
+
+        impl Read for Blocked {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                std::thread::sleep(Duration::from_secs(3600));
+                Ok(0)
+            }
+        }
+
+        for dispatch in [Dispatch::Match, Dispatch::Threaded] {
+            let builder = Interpreter::builder()
+                .dispatch(dispatch)
+                .background_input(Blocked)
+                .timeout(Duration::from_millis(50));
+            let (result, output) = limited("+.,", builder);
+            assert!(matches!(result, Err(Error::TimedOut)));
+            assert_eq!(output, vec![1]);
+        }
+    }
+
+    #[test]
+    fn test_step_until_io() {
+        let mut parser = Parser::new(Lexer::new(String::from("+.,[.,]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(1));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::NeedsInput);
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::NeedsInput);
+
+        interpreter.push_input(b"hi");
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(b'h'));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(b'i'));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::NeedsInput);
+
+        interpreter.close_input();
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Halted);
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Halted);
+    }
+
+    #[test]
+    fn test_output_bytes() {
+        let program = vec![
+            Instruction::OutputBytes(b"hi".to_vec()),
+            Instruction::Output,
+        ];
+
+        let mut interpreter = Interpreter::new_with_instructions(program.clone());
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(b'h'));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(b'i'));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Output(0));
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Halted);
+        assert_eq!(interpreter.steps(), 2);
+        assert_eq!(interpreter.io_positions(), (0, 3));
+
+        let mut output = Vec::new();
+        Interpreter::builder()
+            .output(&mut output)
+            .build_with_instructions(program)
+            .run()
+            .unwrap();
+        assert_eq!(output, b"hi\0");
+    }
+
+    #[test]
+    fn test_step_until_io_limits() {
+        let mut parser = Parser::new(Lexer::new(String::from("+[]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::builder().max_steps(100).build(ast.data);
+
+        assert!(matches!(
+            interpreter.step_until_io(),
+            Err(Error::StepLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_step() {
+        let mut parser = Parser::new(Lexer::new(String::from("+>.[-]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+
+        assert_eq!(interpreter.step().unwrap(), Event::Paused);
+        assert_eq!(interpreter.program_counter(), 1);
+        assert_eq!(interpreter.step().unwrap(), Event::Paused);
+        assert_eq!(interpreter.memory_pointer(), 1);
+        assert_eq!(interpreter.step().unwrap(), Event::Output(0));
+
+        // the loop is skipped, which ends the program
+        assert_eq!(interpreter.step().unwrap(), Event::Halted);
+        assert_eq!(interpreter.program_counter(), 6);
+        assert_eq!(interpreter.steps(), 4);
+        assert_eq!(interpreter.step().unwrap(), Event::Halted);
+        assert_eq!(interpreter.steps(), 4);
+
+        // pausing doesn't leak into later runs
+        assert_eq!(interpreter.step_until_io().unwrap(), Event::Halted);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut parser = Parser::new(Lexer::new(String::from("++>+<[->+<]>.")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+
+        let value = interpreter.add_watchpoint(Watchpoint::Value { cell: 1, value: 3 });
+        let read = interpreter.add_watchpoint(Watchpoint::Read(1));
+        let pointer = interpreter.add_watchpoint(Watchpoint::Pointer { start: 1, end: 2 });
+
+        let mut hits = Vec::new();
+        while interpreter.step().unwrap() != Event::Halted {
+            for id in interpreter.watch_hits() {
+                hits.push((*id, interpreter.last_access().unwrap().program_counter));
+            }
+        }
+
+        // `>` at 1, 6 and 10, `+` at 7 on the second iteration and `.` at 11
+        assert_eq!(
+            hits,
+            vec![
+                (pointer, 1),
+                (read, 2),
+                (pointer, 6),
+                (read, 7),
+                (pointer, 6),
+                (value, 7),
+                (read, 7),
+                (pointer, 10),
+                (read, 11),
+            ]
+        );
+
+        assert!(interpreter.remove_watchpoint(read));
+        assert!(!interpreter.remove_watchpoint(read));
+        assert_eq!(interpreter.watchpoints().len(), 2);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut parser = Parser::new(Lexer::new(String::from("+++[>,<-]>[>]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_journaling(true);
+        interpreter.push_input(b"abc");
+
+        while interpreter.step().unwrap() != Event::Halted {}
+        assert_eq!(interpreter.memory()[..2], [0, b'c']);
+        assert_eq!(interpreter.memory_pointer(), 2);
+
+        let steps = interpreter.steps();
+        assert_eq!(interpreter.journal_len() as u64, steps);
+
+        // back to right after the second `,`
+        for _ in 0..12 {
+            interpreter.step_back().unwrap();
+        }
+        assert_eq!(interpreter.memory()[..2], [2, b'b']);
+        assert_eq!(interpreter.memory_pointer(), 1);
+        assert_eq!(interpreter.program_counter(), 4);
+        assert_eq!(interpreter.steps(), steps - 12);
+
+        // replaying consumes the same input again
+        while interpreter.step().unwrap() != Event::Halted {}
+        assert_eq!(interpreter.memory()[..2], [0, b'c']);
+
+        while interpreter.step_back().is_some() {}
+        assert_eq!(interpreter.memory()[..2], [0, 0]);
+        assert_eq!(interpreter.program_counter(), 0);
+        assert_eq!(interpreter.steps(), 0);
+    }
+
+    #[test]
+    fn test_journal_run() {
+        let mut parser = Parser::new(Lexer::new(String::from("+++[>++<-]>.")));
+        let ast = parser.parse_helper();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::builder().output(&mut output).build(ast.data);
+        interpreter.set_journaling(true);
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.journal_len() as u64, interpreter.steps());
+
+        while interpreter.step_back().is_some() {}
+        assert_eq!(interpreter.memory()[..2], [0, 0]);
+        assert_eq!(interpreter.program_counter(), 0);
+        drop(interpreter);
+        assert_eq!(output, [6]);
+    }
+
+    #[test]
+    fn test_journal_limit() {
+        let mut parser = Parser::new(Lexer::new(String::from("+++[>++<-]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_journaling(true);
+        interpreter.set_journal_limit(4);
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.journal_len(), 4);
+        let steps = interpreter.steps();
+
+        // only the end of the last iteration can be undone
+        while interpreter.step_back().is_some() {}
+        assert_eq!(interpreter.memory()[..2], [1, 4]);
+        assert_eq!(interpreter.steps(), steps - 4);
+    }
+
+    #[test]
+    fn test_journal_off() {
+        let mut parser = Parser::new(Lexer::new(String::from("+++[>++<-]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_journaling(true);
+        interpreter.set_journal_limit(0);
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.journal_len(), 0);
+        assert!(interpreter.step_back().is_none());
+    }
+
+    #[test]
+    fn test_journal_error() {
+        let mut parser = Parser::new(Lexer::new(String::from("+>>")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::builder().tape_size(2).build(ast.data);
+        interpreter.set_journaling(true);
+
+        // the move that failed is undone first
+        assert!(matches!(interpreter.run(), Err(Error::PointerOutOfBounds)));
+        let entry = interpreter.step_back().unwrap();
+        assert_eq!(entry.access.program_counter, 1);
+        assert_eq!(interpreter.memory_pointer(), 0);
+        assert_eq!(interpreter.program_counter(), 1);
+        assert_eq!(interpreter.memory()[0], 1);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        fn build<'a>(input: &'a [u8], output: &'a mut Vec<u8>, max_steps: u64) -> Interpreter<'a> {
+            let mut parser = Parser::new(Lexer::new(String::from("+[>+.,<]")));
+            let ast = parser.parse_helper();
+            Interpreter::builder()
+                .input(input)
+                .output(output)
+                .max_steps(max_steps)
+                .build(ast.data)
+        }
+
+        let mut first = Vec::new();
+        let mut interpreter = build(b"abcdef", &mut first, 20);
+        assert!(matches!(interpreter.run(), Err(Error::StepLimitExceeded)));
+        let snapshot = interpreter.snapshot();
+        drop(interpreter);
+
+        // three full iterations of the loop body
+        assert_eq!(snapshot.steps, 20);
+        assert_eq!(snapshot.program_counter, 2);
+        assert_eq!(snapshot.memory_pointer, 0);
+        assert_eq!(snapshot.input_position, 3);
+        assert_eq!(snapshot.output_position, 3);
+        assert_eq!(first, b"\x01bc");
+
+        // a fresh interpreter continues where the first one stopped, reading
+        // the rest of the input; the step limit counts from the start
+        let mut second = Vec::new();
+        let mut interpreter = build(b"def", &mut second, 26);
+        interpreter.restore(&snapshot).unwrap();
+        assert_eq!(interpreter.memory()[..2], [1, b'c']);
+        assert!(matches!(interpreter.run(), Err(Error::StepLimitExceeded)));
+        assert_eq!(interpreter.io_positions(), (4, 4));
+        drop(interpreter);
+        assert_eq!(second, b"d");
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut parser = Parser::new(Lexer::new(String::from("++[->+++[-]<]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_profiling(true);
+        interpreter.run().unwrap();
+
+        // + [ - > + [ - ] < ]
+        let counts = interpreter.profile().unwrap();
+        assert_eq!(counts, [1, 1, 2, 2, 2, 2, 6, 6, 2, 2]);
+        assert_eq!(counts.iter().sum::<u64>(), interpreter.steps());
+
+        interpreter.set_profiling(false);
+        assert!(interpreter.profile().is_none());
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut parser = Parser::new(Lexer::new(String::from("++[->+++[-]<]+[-]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_tracing(Some(3));
+        interpreter.run().unwrap();
+
+        let event = |program_counter, depth, start, steps| TraceEvent {
+            program_counter,
+            depth,
+            start,
+            steps,
+        };
+        assert_eq!(
+            interpreter.take_trace(),
+            [
+                event(5, 1, 5, 7),
+                event(5, 1, 17, 7),
+                event(1, 0, 1, 25),
+                event(11, 0, 27, 3),
+            ]
+        );
+        assert!(interpreter.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let mut parser = Parser::new(Lexer::new(String::from("++[->+<]>>,.[>]")));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::builder()
+            .input(&b"a"[..])
+            .output(std::io::sink())
+            .build(ast.data);
+        interpreter.set_heatmap(true);
+        interpreter.run().unwrap();
+
+        let heatmap = interpreter.heatmap().unwrap();
+        assert_eq!(heatmap.reads[..4], [6, 2, 2, 1]);
+        assert_eq!(heatmap.writes[..4], [3, 2, 1, 0]);
+        assert_eq!(heatmap.max_pointer, 3);
+        assert_eq!(heatmap.used(), Some((0, 3)));
+    }
+
+    #[test]
+    fn test_instruction_display() {
+        let program = [
+            Instruction::Incr(2),
+            Instruction::BeginLoop(3),
+            Instruction::Next(3),
+            Instruction::Set(1),
+            Instruction::EndLoop(3),
+            Instruction::ScanLeft,
+            Instruction::Output,
+        ];
+        let text: String = program.iter().map(ToString::to_string).collect();
+        assert_eq!(text, "++[>>>[-]+][<].");
+    }
+
+    #[test]
+    fn test_restore_mismatch() {
+        let snapshot = Interpreter::new_with_instructions(vec![Instruction::Incr(1)]).snapshot();
+        let mut interpreter = Interpreter::new_with_instructions(vec![Instruction::Incr(2)]);
+        assert!(matches!(
+            interpreter.restore(&snapshot),
+            Err(Error::SnapshotMismatch)
+        ));
+
+        let mut interpreter = Interpreter::new_with_instructions(vec![Instruction::Incr(1)]);
+        let invalid = Snapshot {
+            memory_pointer: DEFAULT_TAPE_SIZE,
+            ..snapshot
+        };
+        assert!(matches!(
+            interpreter.restore(&invalid),
+            Err(Error::InvalidSnapshot)
+        ));
+    }
+
+    /// Records every chunk handed to the underlying writer
+    struct Chunks<'a>(&'a mut Vec<Vec<u8>>);
+
+    impl Write for Chunks<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output_chunks(program: &str, flush_policy: FlushPolicy) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        let mut parser = Parser::new(Lexer::new(String::from(program)));
+        let ast = parser.parse_helper();
+
+        Interpreter::builder()
+            .output(Chunks(&mut chunks))
+            .flush_policy(flush_policy)
+            .build(ast.data)
+            .run()
+            .unwrap();
+
+        chunks
+    }
+
+    #[test]
+    fn test_flush_policy() {
+        // "a\nb\n"
+        let program = "++++++++++[>++++++++++<-]>---.<++++++++++.>+.<.";
+
+        assert_eq!(
+            output_chunks(program, FlushPolicy::Exit),
+            vec![b"a\nb\n".to_vec()]
+        );
+        assert_eq!(
+            output_chunks(program, FlushPolicy::Newline),
+            vec![b"a\n".to_vec(), b"b\n".to_vec()]
+        );
+        assert_eq!(output_chunks(program, FlushPolicy::Always).len(), 4);
+    }
+
+    #[test]
+    fn test_flush_before_input() {
+        let mut chunks = Vec::new();
+        let mut parser = Parser::new(Lexer::new(String::from("+.,.")));
+        let ast = parser.parse_helper();
+
+        Interpreter::builder()
+            .input(&b"x"[..])
+            .output(Chunks(&mut chunks))
+            .build(ast.data)
+            .run()
+            .unwrap();
+
+        assert_eq!(chunks, vec![vec![1], b"x".to_vec()]);
+    }
+
+    #[test]
+    fn test_dispatch() {
+        use crate::optimizer::{Optimizer, OptimizerOptions};
+
+        fn run(program: &[Instruction], dispatch: Dispatch, max_steps: u64) -> (Snapshot, Vec<u8>) {
+            let mut output = Vec::new();
+            let mut interpreter = Interpreter::builder()
+                .input(&b"ab"[..])
+                .output(&mut output)
+                .tape_size(16)
+                .max_steps(max_steps)
+                .dispatch(dispatch)
+                .build_with_instructions(program.to_vec());
+            let _ = interpreter.run();
+            let snapshot = interpreter.snapshot();
+            drop(interpreter);
+            (snapshot, output)
+        }
+
+        let programs = [
+            "++>+++[-<+>>+<]>[>]<<[<]>,.,.+>[>+<-]+<",
+            "+++[>+>+<<-]>>[[-]<<]<[-]-[>+]",
+            "+[<+]",
+            "+>+>+[<]",
+            "++[>++[>++[>+<-]<-]<-]>>>[<<<+>>>-]<<<.",
+            "+>+<[<]",
+            "+[+>]",
+            "+[-<]",
+            "++[-]+++>>+>[-]>>+[>]",
+            "++++++++++[>++++++<-]>+.+.<++++++++++.,.",
+            ",[>+<[-]]+.>.,[>[-]<[->+<]]>-",
+            ",[-]++.+.>,.[-]+++.<.",
+        ];
+        // without partial evaluation most of these would run while optimizing
+        let options = [
+            OptimizerOptions {
+                partial_eval: false,
+                ..Default::default()
+            },
+            OptimizerOptions::default(),
+        ];
+        for (source, options) in programs.into_iter().flat_map(|p| options.map(|o| (p, o))) {
+            let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+            let mut optimizer = Optimizer::new(&ast.data);
+            optimizer.optimize_with(&options);
+            let program = optimizer.compile(&options);
+
+            // stopping after every possible step must leave the same state
+            for max_steps in 1..150 {
+                let (expected, expected_output) = run(&program, Dispatch::Match, max_steps);
+                let (actual, actual_output) = run(&program, Dispatch::Threaded, max_steps);
+                assert_eq!(actual_output, expected_output, "{} {}", source, max_steps);
+                assert_eq!(actual.steps, expected.steps, "{} {}", source, max_steps);
+                assert_eq!(actual.program_counter, expected.program_counter);
+                assert_eq!(actual.memory_pointer, expected.memory_pointer);
+                assert_eq!(actual.memory, expected.memory);
+                assert_eq!(actual.input_position, expected.input_position);
+                assert_eq!(actual.output_position, expected.output_position);
+            }
+        }
     }
 }
@@ -0,0 +1,22 @@
+use super::watchpoint::Access;
+
+/// Everything needed to undo one instruction executed by `Interpreter::step`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JournalEntry {
+    pub access: Access,
+    /// Pending input byte the instruction consumed
+    pub input: Option<u8>,
+}
+
+/// State before the instruction `Interpreter::execute` is running, journaled
+/// once it's done
+#[derive(Debug, Clone, Copy)]
+pub struct JournalStart {
+    pub program_counter: usize,
+    pub pointer: usize,
+    pub value: u8,
+    pub pending_input: usize,
+    /// `Interpreter::steps` with the instruction counted, it's taken back when
+    /// the instruction has to run again
+    pub steps: u64,
+}
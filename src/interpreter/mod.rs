@@ -1,3 +1,18 @@
+mod heatmap;
+mod input;
 mod interpreter;
+mod journal;
+mod snapshot;
+mod threaded;
+mod trace;
+mod watchpoint;
 
-pub use self::interpreter::Interpreter;
+pub use self::heatmap::TapeHeatmap;
+pub use self::interpreter::{
+    run_bytes, CancelHandle, Dispatch, Error, Event, FlushPolicy, Instruction, Interpreter,
+    InterpreterBuilder, DEFAULT_JOURNAL_LIMIT, DEFAULT_TAPE_SIZE,
+};
+pub use self::journal::JournalEntry;
+pub use self::snapshot::{program_hash, Snapshot};
+pub use self::trace::TraceEvent;
+pub use self::watchpoint::{Access, Watchpoint};
@@ -0,0 +1,205 @@
+use std::io::{self, Read, Write};
+
+use super::interpreter::Instruction;
+
+/// Identifies the snapshot format, the last byte is the version. It changes
+/// whenever the layout or what `program_hash` hashes does.
+const MAGIC: &[u8; 8] = b"fucker\x00\x02";
+
+/// Execution state captured by `Interpreter::snapshot`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// `program_hash` of the program the snapshot was taken from
+    pub program_hash: u64,
+    pub program_counter: usize,
+    pub memory_pointer: usize,
+    pub memory: Vec<u8>,
+    pub steps: u64,
+    /// Bytes the program has read so far
+    pub input_position: u64,
+    /// Bytes the program has written so far
+    pub output_position: u64,
+    /// Input pushed with `Interpreter::push_input` but not read yet
+    pub pending_input: Vec<u8>,
+}
+
+/// Stable FNV-1a hash of a compiled program, so snapshots aren't restored into
+/// a different program
+pub fn program_hash(program: &[Instruction]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+
+    for instruction in program {
+        // superinstructions have a second operand
+        let (tag, operand, second) = match instruction {
+            Instruction::Incr(data) => (0, *data as u64, None),
+            Instruction::Decr(data) => (1, *data as u64, None),
+            Instruction::Next(data) => (2, *data as u64, None),
+            Instruction::Prev(data) => (3, *data as u64, None),
+            Instruction::Input => (4, 0, None),
+            Instruction::Output => (5, 0, None),
+            Instruction::BeginLoop(offset) => (6, *offset as u64, None),
+            Instruction::EndLoop(offset) => (7, *offset as u64, None),
+            Instruction::ScanRight => (8, 0, None),
+            Instruction::ScanLeft => (9, 0, None),
+            Instruction::Set(data) => (10, *data as u64, None),
+            Instruction::SetNext(data, offset) => (11, *data as u64, Some(*offset as u64)),
+            Instruction::SetPrev(data, offset) => (12, *data as u64, Some(*offset as u64)),
+            Instruction::NextEndLoop(data, offset) => (13, *data as u64, Some(*offset as u64)),
+            Instruction::PrevEndLoop(data, offset) => (14, *data as u64, Some(*offset as u64)),
+            Instruction::DecrEndLoop(data, offset) => (15, *data as u64, Some(*offset as u64)),
+            Instruction::NextScanRight(data) => (16, *data as u64, None),
+            Instruction::PrevScanLeft(data) => (17, *data as u64, None),
+            Instruction::OutputBytes(bytes) => (18, bytes.len() as u64, None),
+            Instruction::If(offset) => (19, *offset as u64, None),
+        };
+        feed(&[tag]);
+        feed(&operand.to_le_bytes());
+        if let Some(second) = second {
+            feed(&second.to_le_bytes());
+        }
+        if let Instruction::OutputBytes(bytes) = instruction {
+            feed(bytes);
+        }
+    }
+
+    hash
+}
+
+impl Snapshot {
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        for value in [
+            self.program_hash,
+            self.program_counter as u64,
+            self.memory_pointer as u64,
+            self.steps,
+            self.input_position,
+            self.output_position,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        for bytes in [&self.pending_input, &self.memory] {
+            writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            writer.write_all(bytes)?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn read_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a fucker snapshot, or from an unsupported version",
+            ));
+        }
+
+        let mut read_u64 = || -> io::Result<u64> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let program_hash = read_u64()?;
+        let program_counter = read_u64()? as usize;
+        let memory_pointer = read_u64()? as usize;
+        let steps = read_u64()?;
+        let input_position = read_u64()?;
+        let output_position = read_u64()?;
+
+        let mut read_bytes = || -> io::Result<Vec<u8>> {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+
+            let mut bytes = Vec::new();
+            let len = u64::from_le_bytes(len);
+            reader.take(len).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(bytes)
+        };
+        let pending_input = read_bytes()?;
+        let memory = read_bytes()?;
+
+        Ok(Snapshot {
+            program_hash,
+            program_counter,
+            memory_pointer,
+            memory,
+            steps,
+            input_position,
+            output_position,
+            pending_input,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            program_hash: 42,
+            program_counter: 7,
+            memory_pointer: 3,
+            memory: vec![1, 2, 3, 4, 0, 0],
+            steps: 1000,
+            input_position: 2,
+            output_position: 5,
+            pending_input: b"abc".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut bytes = Vec::new();
+        snapshot().write_to(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(Snapshot::read_from(&mut &bytes[..]).unwrap(), snapshot());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut bytes = Vec::new();
+        snapshot().write_to(&mut bytes).unwrap();
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(Snapshot::read_from(&mut &truncated[..]).is_err());
+
+        bytes[0] = b'F';
+        assert!(Snapshot::read_from(&mut &bytes[..]).is_err());
+
+        // an older version hashed programs differently
+        bytes[..8].copy_from_slice(b"fucker\x00\x01");
+        assert!(Snapshot::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_program_hash() {
+        let hash = program_hash(&[Instruction::Incr(1), Instruction::Output]);
+
+        assert_eq!(
+            hash,
+            program_hash(&[Instruction::Incr(1), Instruction::Output])
+        );
+        assert_ne!(
+            hash,
+            program_hash(&[Instruction::Incr(2), Instruction::Output])
+        );
+        assert_ne!(
+            hash,
+            program_hash(&[Instruction::Output, Instruction::Incr(1)])
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use super::interpreter::Instruction;
+
+/// Most instructions a single op executes, see `Op::AddMove`
+pub(super) const MAX_OP_STEPS: u64 = 2;
+
+/// Marks instructions that don't start an op in `Threaded::op_at`
+pub(super) const NO_OP: usize = usize::MAX;
+
+/// Pre-decoded form of an instruction used by `Interpreter::run`. Jumps hold
+/// the absolute index of the op they continue at, and some common pairs of
+/// instructions are fused into a single op.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Op {
+    /// `Incr` or `Decr`, as a wrapping add
+    Add(u8),
+    /// `Next` or `Prev`
+    Move(isize),
+    /// `Incr` or `Decr` followed by `Next` or `Prev`
+    AddMove(u8, isize),
+    Set(u8),
+    JumpIfZero(usize),
+    JumpIfNotZero(usize),
+    ScanRight,
+    ScanLeft,
+    Input,
+    Output,
+    /// `SetNext` or `SetPrev`
+    SetMove(u8, isize),
+    /// `NextEndLoop` or `PrevEndLoop`
+    MoveJumpIfNotZero(isize, usize),
+    /// `DecrEndLoop`
+    AddJumpIfNotZero(u8, usize),
+    /// `NextScanRight`
+    MoveScanRight(usize),
+    /// `PrevScanLeft`
+    MoveScanLeft(usize),
+    /// `OutputBytes`, as a range of `Threaded::data`
+    OutputBytes(usize, usize),
+}
+
+/// Ops for a program, with the mapping between ops and instructions
+#[derive(Debug, Default)]
+pub(super) struct Threaded {
+    pub(super) ops: Vec<Op>,
+    /// Index of the first instruction of every op, followed by the length of
+    /// the program
+    pub(super) pcs: Vec<usize>,
+    /// Op starting at every instruction, or `NO_OP`, followed by `ops.len()`
+    pub(super) op_at: Vec<usize>,
+    /// Bytes of every `OutputBytes`
+    pub(super) data: Vec<u8>,
+}
+
+fn delta(instruction: &Instruction) -> Option<u8> {
+    match *instruction {
+        Instruction::Incr(n) => Some(n),
+        Instruction::Decr(n) => Some(n.wrapping_neg()),
+        _ => None,
+    }
+}
+
+fn offset(instruction: &Instruction) -> Option<isize> {
+    match *instruction {
+        Instruction::Next(n) => Some(n as isize),
+        Instruction::Prev(n) => Some(-(n as isize)),
+        _ => None,
+    }
+}
+
+impl Threaded {
+    pub(super) fn new(program: &[Instruction]) -> Self {
+        let mut threaded = Threaded {
+            op_at: vec![NO_OP; program.len() + 1],
+            ..Default::default()
+        };
+
+        // an `If` jumps to the end of its body, which mustn't be fused with
+        // the instruction before it
+        let mut if_targets = vec![false; program.len() + 1];
+        for (pc, instruction) in program.iter().enumerate() {
+            if let Instruction::If(jump) = *instruction {
+                if_targets[pc + jump + 1] = true;
+            }
+        }
+
+        // jump targets are instruction indices until every op has been placed
+        let mut pc = 0;
+        while pc < program.len() {
+            let next = program.get(pc + 1);
+            let (op, len) = match (&program[pc], next) {
+                (instruction, Some(next))
+                    if delta(instruction).is_some()
+                        && offset(next).is_some()
+                        && !if_targets[pc + 1] =>
+                {
+                    (
+                        Op::AddMove(delta(instruction).unwrap(), offset(next).unwrap()),
+                        2,
+                    )
+                }
+                (instruction, _) => {
+                    let op = match *instruction {
+                        Instruction::Incr(_) | Instruction::Decr(_) => {
+                            Op::Add(delta(instruction).unwrap())
+                        }
+                        Instruction::Next(_) | Instruction::Prev(_) => {
+                            Op::Move(offset(instruction).unwrap())
+                        }
+                        Instruction::Set(n) => Op::Set(n),
+                        Instruction::BeginLoop(jump) | Instruction::If(jump) => {
+                            Op::JumpIfZero(pc + jump + 1)
+                        }
+                        Instruction::EndLoop(jump) => Op::JumpIfNotZero(pc - jump + 1),
+                        Instruction::ScanRight => Op::ScanRight,
+                        Instruction::ScanLeft => Op::ScanLeft,
+                        Instruction::Input => Op::Input,
+                        Instruction::Output => Op::Output,
+                        Instruction::SetNext(n, k) => Op::SetMove(n, k as isize),
+                        Instruction::SetPrev(n, k) => Op::SetMove(n, -(k as isize)),
+                        Instruction::NextEndLoop(k, jump) => {
+                            Op::MoveJumpIfNotZero(k as isize, pc - jump + 1)
+                        }
+                        Instruction::PrevEndLoop(k, jump) => {
+                            Op::MoveJumpIfNotZero(-(k as isize), pc - jump + 1)
+                        }
+                        Instruction::DecrEndLoop(n, jump) => {
+                            Op::AddJumpIfNotZero(n.wrapping_neg(), pc - jump + 1)
+                        }
+                        Instruction::NextScanRight(k) => Op::MoveScanRight(k),
+                        Instruction::PrevScanLeft(k) => Op::MoveScanLeft(k),
+                        Instruction::OutputBytes(ref bytes) => {
+                            let start = threaded.data.len();
+                            threaded.data.extend_from_slice(bytes);
+                            Op::OutputBytes(start, threaded.data.len())
+                        }
+                    };
+                    (op, 1)
+                }
+            };
+
+            threaded.op_at[pc] = threaded.ops.len();
+            threaded.ops.push(op);
+            threaded.pcs.push(pc);
+            pc += len;
+        }
+        threaded.op_at[program.len()] = threaded.ops.len();
+        threaded.pcs.push(program.len());
+
+        // every jump lands right after a `BeginLoop`, `EndLoop` or `If` body,
+        // which never sits in the middle of an op
+        for op in &mut threaded.ops {
+            match op {
+                Op::JumpIfZero(target)
+                | Op::JumpIfNotZero(target)
+                | Op::MoveJumpIfNotZero(_, target)
+                | Op::AddJumpIfNotZero(_, target) => {
+                    *target = threaded.op_at[*target];
+                    debug_assert_ne!(*target, NO_OP);
+                }
+                _ => {}
+            }
+        }
+
+        threaded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // [->>>+<<<]>
+        let program = [
+            Instruction::BeginLoop(5),
+            Instruction::Decr(1),
+            Instruction::Next(3),
+            Instruction::Incr(1),
+            Instruction::Prev(3),
+            Instruction::EndLoop(5),
+            Instruction::Next(1),
+        ];
+        let threaded = Threaded::new(&program);
+
+        assert_eq!(
+            threaded.ops,
+            [
+                Op::JumpIfZero(4),
+                Op::AddMove(255, 3),
+                Op::AddMove(1, -3),
+                Op::JumpIfNotZero(1),
+                Op::Move(1),
+            ]
+        );
+        assert_eq!(threaded.pcs, [0, 1, 3, 5, 6, 7]);
+        assert_eq!(threaded.op_at, [0, 1, NO_OP, 2, NO_OP, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_decode_move_jump() {
+        // +[>]  without the scan optimization, then [<[-]]
+        let program = [
+            Instruction::Incr(1),
+            Instruction::BeginLoop(1),
+            Instruction::NextEndLoop(1, 1),
+            Instruction::BeginLoop(3),
+            Instruction::Prev(1),
+            Instruction::Set(0),
+            Instruction::EndLoop(3),
+        ];
+        let threaded = Threaded::new(&program);
+
+        assert_eq!(
+            threaded.ops,
+            [
+                Op::Add(1),
+                Op::JumpIfZero(3),
+                Op::MoveJumpIfNotZero(1, 2),
+                Op::JumpIfZero(7),
+                Op::Move(-1),
+                Op::Set(0),
+                Op::JumpIfNotZero(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_if() {
+        // an `If` around >+, then <
+        let program = [
+            Instruction::If(2),
+            Instruction::Next(1),
+            Instruction::Incr(1),
+            Instruction::Prev(1),
+        ];
+        let threaded = Threaded::new(&program);
+
+        // the skipped body ends between + and <, so they aren't fused
+        assert_eq!(
+            threaded.ops,
+            [Op::JumpIfZero(3), Op::Move(1), Op::Add(1), Op::Move(-1),]
+        );
+    }
+}
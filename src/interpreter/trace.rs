@@ -0,0 +1,57 @@
+/// One run of a loop, from entering its body until leaving it. Times are
+/// counted in executed instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Index of the loop's `BeginLoop`
+    pub program_counter: usize,
+    pub depth: usize,
+    pub start: u64,
+    pub steps: u64,
+}
+
+/// Records loop runs as the interpreter enters and leaves them
+#[derive(Debug, Default)]
+pub(super) struct Tracer {
+    min_steps: u64,
+    open: Vec<(usize, u64)>,
+    events: Vec<TraceEvent>,
+}
+
+impl Tracer {
+    pub(super) fn new(min_steps: u64) -> Self {
+        Tracer {
+            min_steps,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn enter(&mut self, program_counter: usize, steps: u64) {
+        self.open.push((program_counter, steps));
+    }
+
+    pub(super) fn leave(&mut self, steps: u64) {
+        // loops entered before a snapshot was restored are never opened
+        if let Some((program_counter, start)) = self.open.pop() {
+            self.record(program_counter, start, steps);
+        }
+    }
+
+    /// Closes loops that are still running, e.g. after a timeout
+    pub(super) fn finish(&mut self, steps: u64) -> Vec<TraceEvent> {
+        while !self.open.is_empty() {
+            self.leave(steps);
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    fn record(&mut self, program_counter: usize, start: u64, end: u64) {
+        if end - start >= self.min_steps {
+            self.events.push(TraceEvent {
+                program_counter,
+                depth: self.open.len(),
+                start,
+                steps: end - start,
+            });
+        }
+    }
+}
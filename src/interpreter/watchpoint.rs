@@ -0,0 +1,103 @@
+/// What a single instruction executed by `Interpreter::step` did to the tape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    /// Index of the executed instruction
+    pub program_counter: usize,
+    /// Inclusive range of cells read
+    pub read: Option<(usize, usize)>,
+    /// Cell written, with its value before and after the write
+    pub write: Option<(usize, u8, u8)>,
+    /// Memory pointer before and after the instruction
+    pub pointer: (usize, usize),
+}
+
+/// Condition that makes `Interpreter::step` report a hit, see `Interpreter::watch_hits`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    /// The cell is read
+    Read(usize),
+    /// The cell is written, even if its value stays the same
+    Write(usize),
+    /// A write changes the cell to `value`
+    Value { cell: usize, value: u8 },
+    /// The memory pointer moves into `start..end` from outside of it
+    Pointer { start: usize, end: usize },
+}
+
+impl Watchpoint {
+    pub fn triggered_by(&self, access: &Access) -> bool {
+        match *self {
+            Watchpoint::Read(cell) => access
+                .read
+                .is_some_and(|(first, last)| (first..=last).contains(&cell)),
+            Watchpoint::Write(cell) => access.write.is_some_and(|(written, _, _)| written == cell),
+            Watchpoint::Value { cell, value } => access
+                .write
+                .is_some_and(|(written, old, new)| written == cell && old != value && new == value),
+            Watchpoint::Pointer { start, end } => {
+                let (before, after) = access.pointer;
+                !(start..end).contains(&before) && (start..end).contains(&after)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Watchpoint::Read(cell) => write!(f, "read of cell {}", cell),
+            Watchpoint::Write(cell) => write!(f, "write to cell {}", cell),
+            Watchpoint::Value { cell, value } => write!(f, "cell {} becoming {}", cell, value),
+            Watchpoint::Pointer { start, end } => write!(f, "pointer entering {}..{}", start, end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(
+        read: Option<(usize, usize)>,
+        write: Option<(usize, u8, u8)>,
+        pointer: (usize, usize),
+    ) -> Access {
+        Access {
+            program_counter: 0,
+            read,
+            write,
+            pointer,
+        }
+    }
+
+    #[test]
+    fn test_read_write() {
+        let scan = access(Some((3, 7)), None, (3, 7));
+        assert!(Watchpoint::Read(5).triggered_by(&scan));
+        assert!(!Watchpoint::Read(8).triggered_by(&scan));
+        assert!(!Watchpoint::Write(5).triggered_by(&scan));
+
+        let set = access(None, Some((2, 0, 0)), (2, 2));
+        assert!(Watchpoint::Write(2).triggered_by(&set));
+        assert!(!Watchpoint::Read(2).triggered_by(&set));
+    }
+
+    #[test]
+    fn test_value() {
+        let watch = Watchpoint::Value { cell: 1, value: 10 };
+
+        assert!(watch.triggered_by(&access(None, Some((1, 9, 10)), (1, 1))));
+        assert!(!watch.triggered_by(&access(None, Some((1, 10, 10)), (1, 1))));
+        assert!(!watch.triggered_by(&access(None, Some((2, 9, 10)), (2, 2))));
+    }
+
+    #[test]
+    fn test_pointer() {
+        let watch = Watchpoint::Pointer { start: 10, end: 20 };
+
+        assert!(watch.triggered_by(&access(None, None, (9, 10))));
+        assert!(watch.triggered_by(&access(None, None, (25, 19))));
+        assert!(!watch.triggered_by(&access(None, None, (10, 11))));
+        assert!(!watch.triggered_by(&access(None, None, (19, 20))));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::parser::ASTNode;
+
+/// Optimization IR between `ASTNode` and the backends. Parser artifacts are
+/// gone, cell changes are signed and can address cells relative to the
+/// pointer, and loops know how far one iteration moves the pointer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Adds `delta`, wrapping, to the cell at `offset`
+    Add {
+        offset: isize,
+        delta: i8,
+    },
+    /// Stores `value` in the cell at `offset`
+    Set {
+        offset: isize,
+        value: u8,
+    },
+    /// Moves the pointer
+    Move(isize),
+    /// Reads a byte into the cell at `offset`
+    Input {
+        offset: isize,
+    },
+    /// Writes the cell at `offset`
+    Output {
+        offset: isize,
+    },
+    /// Writes bytes known when optimizing, see `optimizer::coalesce_output`
+    OutputBytes(Vec<u8>),
+    /// Moves the pointer by `step` until it's on a zero cell
+    Scan(isize),
+    Loop(Loop),
+    /// Runs the body once unless the current cell is zero, the body is always
+    /// balanced
+    If(Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub body: Vec<Node>,
+    /// Net pointer movement of one iteration, `None` when it depends on the
+    /// tape because the body contains a scan or an unbalanced loop
+    pub shift: Option<isize>,
+}
+
+impl Loop {
+    pub fn new(body: Vec<Node>) -> Self {
+        let shift = shift(&body);
+        Loop { body, shift }
+    }
+
+    /// Whether every iteration ends on the cell it started on
+    pub fn is_balanced(&self) -> bool {
+        self.shift == Some(0)
+    }
+}
+
+impl Node {
+    /// Offset of the cell the node works on, 0 for nodes that only work on
+    /// the current cell or don't touch cells at all
+    pub fn offset(&self) -> isize {
+        match *self {
+            Node::Add { offset, .. }
+            | Node::Set { offset, .. }
+            | Node::Input { offset }
+            | Node::Output { offset } => offset,
+            _ => 0,
+        }
+    }
+}
+
+/// Net pointer movement of `nodes`, if it's known without running them
+fn shift(nodes: &[Node]) -> Option<isize> {
+    nodes.iter().try_fold(0isize, |total, node| match node {
+        Node::Move(offset) => Some(total + offset),
+        Node::Scan(_) => None,
+        Node::Loop(inner) if !inner.is_balanced() => None,
+        Node::If(body) if shift(body) != Some(0) => None,
+        _ => Some(total),
+    })
+}
+
+/// Lowers parsed nodes, dropping comments and `NoOp`s. Every other node becomes exactly one IR node, so source maps
+/// built from the AST stay valid.
+pub fn lower(ast: &[ASTNode]) -> Vec<Node> {
+    ast.iter()
+        .filter_map(|node| {
+            let node = match node {
+                ASTNode::Incr(n) => Node::Add {
+                    offset: 0,
+                    delta: *n as i8,
+                },
+                ASTNode::Decr(n) => Node::Add {
+                    offset: 0,
+                    delta: n.wrapping_neg() as i8,
+                },
+                ASTNode::Next(n) => Node::Move(*n as isize),
+                ASTNode::Prev(n) => Node::Move(-(*n as isize)),
+                ASTNode::Input => Node::Input { offset: 0 },
+                ASTNode::Output => Node::Output { offset: 0 },
+                ASTNode::Loop(body) => Node::Loop(Loop::new(lower(body))),
+                ASTNode::Comment(_) | ASTNode::NoOp => return None,
+            };
+            Some(node)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<Node> {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        lower(&ast.data)
+    }
+
+    #[test]
+    fn test_lower() {
+        assert_eq!(
+            parse("+++ -- > <<, ."),
+            [
+                Node::Add {
+                    offset: 0,
+                    delta: 3
+                },
+                Node::Add {
+                    offset: 0,
+                    delta: -2
+                },
+                Node::Move(1),
+                Node::Move(-2),
+                Node::Input { offset: 0 },
+                Node::Output { offset: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_shift() {
+        let Node::Loop(balanced) = &parse("[->>+<<]")[0] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(balanced.shift, Some(0));
+        assert!(balanced.is_balanced());
+
+        let Node::Loop(moving) = &parse("[>>[-]<]")[0] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(moving.shift, Some(1));
+
+        // the inner loop moves the pointer by an unknown amount
+        let Node::Loop(unknown) = &parse("[>[>]<]")[0] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(unknown.shift, None);
+        assert!(!unknown.is_balanced());
+    }
+}
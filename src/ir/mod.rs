@@ -0,0 +1,3 @@
+mod ir;
+
+pub use self::ir::{lower, Loop, Node};
@@ -13,14 +13,49 @@ pub enum Token {
     Other(char),
 }
 
+/// Where a node came from in the source. `offset` and `len` count characters,
+/// `line` and `column` start at 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
 pub struct Lexer {
     input: String,
     position: usize,
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
-        Lexer { input, position: 0 }
+        Lexer {
+            input,
+            position: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Zero length span at the current position, grown with `extend_span`
+    pub fn start_span(&self) -> Span {
+        Span {
+            offset: self.position,
+            len: 0,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Covers everything consumed since `span` was started
+    pub fn extend_span(&self, span: Span) -> Span {
+        Span {
+            len: self.position - span.offset,
+            ..span
+        }
     }
 
     pub fn peek_token(&self) -> Option<Token> {
@@ -51,6 +86,13 @@ impl Lexer {
         let current_char = self.input.chars().nth(self.position).unwrap();
         self.position += 1;
 
+        if current_char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         match current_char {
             '+' => Some(Token::Incr),
             '-' => Some(Token::Decr),
@@ -104,6 +146,28 @@ mod tests {
         assert_eq!(lexer.next_token(), None);
     }
 
+    #[test]
+    fn test_span() {
+        let mut lexer = Lexer::new(String::from("+\n ++"));
+        lexer.next_token();
+        lexer.next_token();
+        lexer.next_token();
+
+        let span = lexer.start_span();
+        lexer.next_token();
+        lexer.next_token();
+
+        assert_eq!(
+            lexer.extend_span(span),
+            Span {
+                offset: 3,
+                len: 2,
+                line: 2,
+                column: 2
+            }
+        );
+    }
+
     #[test]
     fn test_next_token_other() {
         let mut lexer = Lexer::new(String::from("abc123"));
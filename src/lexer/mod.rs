@@ -1,3 +1,3 @@
 mod lexer;
 
-pub use self::lexer::{Lexer, Token};
+pub use self::lexer::{Lexer, Span, Token};
@@ -0,0 +1,18 @@
+pub mod analysis;
+pub mod cli;
+pub mod compiler;
+pub mod coverage;
+pub mod debugger;
+pub mod emit;
+pub mod interpreter;
+pub mod ir;
+pub mod lexer;
+pub mod nom;
+pub mod optimizer;
+pub mod parser;
+pub mod profiler;
+pub mod tape;
+pub mod util;
+pub mod viz;
+
+pub use interpreter::run_bytes;
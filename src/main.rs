@@ -1,35 +1,386 @@
-use util::{print_debug, unlikely};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::Duration;
 
-mod cli;
-mod compiler;
-mod interpreter;
-mod lexer;
-mod nom;
-mod optimizer;
-mod parser;
-mod util;
+use brainfuck_compiler::{
+    analysis::{self, TapeBounds},
+    cli::{self, AnalyzeArgs, Command, DebugArgs, RunArgs, VizArgs},
+    compiler::Compiler,
+    coverage::Coverage,
+    debugger::{self, Debugger},
+    emit::{self, Stage},
+    interpreter::{self, Interpreter, Snapshot, DEFAULT_TAPE_SIZE},
+    ir, lexer,
+    optimizer::{self, OptimizerOptions},
+    parser::{self, ASTNode, AbstractSyntaxTree},
+    profiler::{self, Profile, TraceFormat},
+    tape,
+    util::{print_debug, print_error, print_note, print_warning, unlikely},
+    viz::Visualizer,
+};
 
 fn main() {
-    let cli = cli::parse_args();
-    let file = cli.file.clone();
-    let input = std::fs::read_to_string(file).unwrap();
+    match cli::parse_args().command {
+        Command::Run(args) => run(args),
+        Command::Debug(args) => debug(args),
+        Command::Viz(args) => viz(args),
+        Command::Analyze(args) => analyze(args),
+    }
+}
+
+fn run(args: RunArgs) {
+    let input = read_source(&args.file);
+
+    if let Some(stage) = args.emit {
+        return emit(&args, stage, &input);
+    }
+
+    let (ast, parse_duration) = parse(&input);
+
+    let bounds = analysis::tape_bounds(&ir::lower(&ast.data));
+    if let Some(index) = bounds.left_of_start {
+        let span = &debugger::source_map(&ast.data, &ast.spans)[index];
+        print_warning(&format!(
+            "the pointer always moves left of cell 0 at {}:{}",
+            span.line, span.column
+        ));
+    }
+    let tape_size = tape_size(&args, &bounds);
+
+    if args.verify_optimizations {
+        return verify_optimizations(&args, &ast.data, tape_size);
+    }
+    print_note(&format!("{}, running with {} cells", bounds, tape_size));
+
+    // coverage and the profile need every instruction to line up with its
+    // source span
+    let (program, spans, opt_duration) = if args.coverage.is_some() || args.profile {
+        let spans = debugger::source_map(&ast.data, &ast.spans);
+        (Interpreter::compile(ast.data), spans, Duration::ZERO)
+    } else {
+        let options = OptimizerOptions {
+            tape_size,
+            max_steps: args.max_steps,
+            snapshots: args.resume.is_some() || args.save_state_on_exit.is_some(),
+            ..OptimizerOptions::from(&args.optimizations)
+        };
+        let mut optimizer = optimizer::Optimizer::new(&ast.data);
+        let opt_duration = optimizer.optimize_with(&options);
+        for warning in &optimizer.warnings {
+            print_warning(warning);
+        }
+        (optimizer.compile(&options), Vec::new(), opt_duration)
+    };
+
+    let mut builder = Interpreter::builder()
+        .flush_policy(args.flush)
+        .tape_size(tape_size);
+    if let Some(max_steps) = args.max_steps {
+        builder = builder.max_steps(max_steps);
+    }
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if args.timeout.is_some() || args.save_state_on_exit.is_some() {
+        // so the timeout and Ctrl-C also stop a program waiting for input
+        builder = builder.background_input(std::io::stdin());
+    }
+    let mut interpreter = builder.build_with_instructions(program);
+
+    if let Some(path) = &args.resume {
+        if let Err(err) = resume(&mut interpreter, path) {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
+    let collapsed = args.trace.is_some() && args.trace_format == TraceFormat::Collapsed;
+    if args.profile || args.coverage.is_some() || collapsed {
+        interpreter.set_profiling(true);
+    }
+    if args.tape_heatmap {
+        interpreter.set_heatmap(true);
+    }
+    if args.trace.is_some() && !collapsed {
+        interpreter.set_tracing(Some(args.trace_min_steps));
+    }
+    if args.save_state_on_exit.is_some() {
+        // Ctrl-C stops the program cleanly so its state can be saved
+        let cancel = interpreter.cancel_handle();
+        ctrlc::set_handler(move || cancel.cancel()).expect("failed to install Ctrl-C handler");
+    }
+
+    let start = std::time::Instant::now();
+    let result = interpreter.run();
+    let exec_duration = start.elapsed();
+
+    if let Some(path) = &args.save_state_on_exit {
+        if let Err(err) = save_state(&interpreter, path) {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
 
-    let lexer = lexer::Lexer::new(input.clone());
-    let mut parser = parser::Parser::new(lexer);
-    let (ast, parse_duration) = parser.parse();
+    if let (true, Some(counts)) = (args.profile, interpreter.profile()) {
+        let profile = Profile::new(interpreter.program(), counts).with_source(&input, &spans);
+        // a closed stderr isn't worth failing the run over
+        let _ = profile.report(args.profile_top, &mut std::io::stderr());
+    }
 
-    let mut optimizer = optimizer::Optimizer::new(ast.data);
-    let opt_duration = optimizer.optimize(&cli);
+    if let Some(range) = &args.dump_tape {
+        let memory = interpreter.memory();
+        let range = range.clone().unwrap_or_else(|| {
+            let last = memory.iter().rposition(|&cell| cell != 0).unwrap_or(0);
+            0..last.max(interpreter.memory_pointer()) + 1
+        });
+        let _ = tape::dump(memory, range, &mut std::io::stderr());
+    }
 
-    let mut interpreter = interpreter::Interpreter::new(optimizer.program);
+    if let Some(heatmap) = interpreter.heatmap() {
+        let _ = tape::report_heatmap(heatmap, &mut std::io::stderr());
+    }
+
+    if let Some(path) = &args.trace {
+        if let Err(err) = write_trace(&mut interpreter, args.trace_format, path) {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
 
-    if unlikely(cli.debug) {
-        let start = std::time::Instant::now();
-        interpreter.run();
-        let exec_duration = start.elapsed();
+    if let (Some(path), Some(counts)) = (&args.coverage, interpreter.profile()) {
+        let coverage = Coverage::new(&input, interpreter.program(), &spans, counts);
+        let _ = coverage.annotate(&mut std::io::stderr());
+        if let Err(err) = write_lcov(&coverage, &args.file, path) {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
 
+    if unlikely(args.debug) {
         print_debug(parse_duration, opt_duration, exec_duration);
+    }
+
+    if let Err(err) = result {
+        print_error(&err);
+        std::process::exit(1);
+    }
+}
+
+/// Contents of `path`, exits when it can't be read
+fn read_source(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|err| {
+        print_error(&format!("{}: {}", path.display(), err));
+        std::process::exit(1);
+    })
+}
+
+/// Parses `source`, exits when its loops are unbalanced
+fn parse(source: &str) -> (AbstractSyntaxTree, Duration) {
+    let start = std::time::Instant::now();
+    let lexer = lexer::Lexer::new(String::from(source));
+    match parser::Parser::new(lexer).try_parse() {
+        Ok(ast) => (ast, start.elapsed()),
+        Err(err) => {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--tape-size`, or only as many cells as the program can reach when that's
+/// known to be fewer than the default
+fn tape_size(args: &RunArgs, bounds: &TapeBounds) -> usize {
+    args.tape_size.unwrap_or_else(|| {
+        bounds
+            .tape_size()
+            .map_or(DEFAULT_TAPE_SIZE, |size| size.min(DEFAULT_TAPE_SIZE))
+    })
+}
+
+fn emit(args: &RunArgs, stage: Stage, input: &str) {
+    let format = args.emit_format;
+    let mut out = std::io::stdout().lock();
+
+    let result = if stage == Stage::Tokens {
+        emit::tokens(input, format, &mut out)
+    } else {
+        let (ast, _) = parse(input);
+
+        let options = OptimizerOptions {
+            tape_size: tape_size(args, &analysis::tape_bounds(&ir::lower(&ast.data))),
+            max_steps: args.max_steps,
+            snapshots: args.resume.is_some() || args.save_state_on_exit.is_some(),
+            ..OptimizerOptions::from(&args.optimizations)
+        };
+        let mut optimizer = optimizer::Optimizer::new(&ast.data);
+        match stage {
+            Stage::Ast => emit::ast(&ast.data, format, &mut out),
+            Stage::OptimizedIr => {
+                optimizer.optimize_with(&options);
+                emit::ir(&optimizer.program, format, &mut out)
+            }
+            Stage::Asm => {
+                optimizer.optimize_with(&options);
+                Compiler::new(optimizer.program).assembly(options.tape_size, &mut out)
+            }
+            _ => {
+                optimizer.optimize_with(&options);
+                let program = optimizer.compile(&options);
+                emit::instructions(&program, format, &mut out)
+            }
+        }
+    };
+
+    match result.and_then(|_| out.flush()) {
+        // piping into `head` is fine
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+        Err(err) => {
+            print_error(&err);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn verify_optimizations(args: &RunArgs, ast: &[ASTNode], tape_size: usize) {
+    let mut input = Vec::new();
+    if let Err(err) = std::io::stdin().read_to_end(&mut input) {
+        print_error(&err);
+        std::process::exit(1);
+    }
+
+    match optimizer::verify(ast, &input, args.max_steps, tape_size) {
+        Ok(output) => {
+            let mut stdout = std::io::stdout();
+            // a closed stdout isn't worth failing the run over
+            let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
+        }
+        Err(err) => {
+            print_error(&err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn resume(interpreter: &mut Interpreter, path: &Path) -> Result<(), interpreter::Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let snapshot = Snapshot::read_from(&mut file)?;
+    interpreter.restore(&snapshot)
+}
+
+fn write_trace(
+    interpreter: &mut Interpreter,
+    format: TraceFormat,
+    path: &Path,
+) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        TraceFormat::Chrome => {
+            let events = interpreter.take_trace();
+            profiler::write_chrome_trace(
+                interpreter.program(),
+                &events,
+                interpreter.steps(),
+                &mut file,
+            )?;
+        }
+        TraceFormat::Collapsed => {
+            let counts = interpreter.profile().unwrap_or_default();
+            let profile = Profile::new(interpreter.program(), counts);
+            profiler::write_collapsed(&profile, interpreter.program(), &mut file)?;
+        }
+    }
+    file.flush()
+}
+
+fn write_lcov(coverage: &Coverage, source: &Path, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    coverage.write_lcov(&source.to_string_lossy(), &mut file)?;
+    file.flush()
+}
+
+fn save_state(interpreter: &Interpreter, path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    interpreter.snapshot().write_to(&mut file)?;
+    file.flush()
+}
+
+fn debug(args: DebugArgs) {
+    let input = read_source(&args.file);
+    let (ast, _) = parse(&input);
+
+    // the optimized program has no comments, so no `#` breakpoints either
+    let (program, ast, spans) = if args.optimized {
+        let mut optimizer = optimizer::Optimizer::new(&ast.data);
+        optimizer.optimize(&args.optimizations);
+        (
+            Interpreter::compile_ir(&optimizer.program),
+            Vec::new(),
+            None,
+        )
+    } else {
+        let spans = debugger::source_map(&ast.data, &ast.spans);
+        (
+            Interpreter::compile(ast.data.clone()),
+            ast.data,
+            Some(spans),
+        )
+    };
+
+    let mut interpreter = Interpreter::new_with_instructions(program);
+    interpreter.set_journal_limit(args.history);
+    let mut debugger = Debugger::new(&input, &ast, spans, interpreter);
+
+    let result = debugger.repl(&mut std::io::stdin().lock(), &mut std::io::stdout());
+    if let Err(err) = result {
+        print_error(&err);
+        std::process::exit(1);
+    }
+}
+
+fn viz(args: VizArgs) {
+    let input = read_source(&args.file);
+    let (ast, _) = parse(&input);
+
+    let (program, spans) = if args.optimized {
+        let mut optimizer = optimizer::Optimizer::new(&ast.data);
+        optimizer.optimize(&args.optimizations);
+        (Interpreter::compile_ir(&optimizer.program), None)
+    } else {
+        let spans = debugger::source_map(&ast.data, &ast.spans);
+        (Interpreter::compile(ast.data), Some(spans))
+    };
+
+    let interpreter = Interpreter::new_with_instructions(program);
+    let mut visualizer = Visualizer::new(&input, spans, interpreter);
+
+    let result = visualizer.run(
+        args.speed,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    );
+    if let Err(err) = result {
+        print_error(&err);
+        std::process::exit(1);
+    }
+}
+
+fn analyze(args: AnalyzeArgs) {
+    let input = read_source(&args.file);
+    let (ast, _) = parse(&input);
+
+    let (program, spans) = if args.optimized {
+        let mut optimizer = optimizer::Optimizer::new(&ast.data);
+        optimizer.optimize(&args.optimizations);
+        (optimizer.program, None)
     } else {
-        interpreter.run();
+        let spans = debugger::source_map(&ast.data, &ast.spans);
+        (ir::lower(&ast.data), Some(spans))
+    };
+
+    let result = analysis::report(&program, spans.as_deref(), &mut std::io::stdout());
+    if let Err(err) = result {
+        print_error(&err);
+        std::process::exit(1);
     }
 }
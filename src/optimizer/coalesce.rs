@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::ir::{Loop, Node};
+
+/// Replaces outputs of cells whose value is known when optimizing by
+/// `Node::OutputBytes`, and merges them while only cell changes come between
+/// them, so `[-]+++.+.+.` writes its three bytes at once.
+///
+/// Values are only tracked from a `Set` up to the next pointer movement that
+/// isn't a fixed `Move`. Outputs aren't merged across moves either, a move can
+/// fail and the bytes after it must not be written then.
+pub fn coalesce_output(nodes: Vec<Node>) -> Vec<Node> {
+    let mut optimized: Vec<Node> = Vec::with_capacity(nodes.len());
+    // known cells by their distance from where the pointer was when
+    // `position` was last reset
+    let mut known: HashMap<isize, u8> = HashMap::new();
+    let mut position = 0;
+    // `OutputBytes` that known outputs are appended to
+    let mut open: Option<usize> = None;
+
+    for node in nodes {
+        let node = match node {
+            Node::Add { offset, delta } => {
+                if let Some(value) = known.get_mut(&(position + offset)) {
+                    *value = value.wrapping_add(delta as u8);
+                }
+                node
+            }
+            Node::Set { offset, value } => {
+                known.insert(position + offset, value);
+                node
+            }
+            Node::Output { offset } => match (known.get(&(position + offset)), open) {
+                (Some(&value), Some(index)) => {
+                    if let Node::OutputBytes(bytes) = &mut optimized[index] {
+                        bytes.push(value);
+                    }
+                    continue;
+                }
+                (Some(&value), None) => {
+                    open = Some(optimized.len());
+                    Node::OutputBytes(vec![value])
+                }
+                (None, _) => {
+                    open = None;
+                    node
+                }
+            },
+            Node::OutputBytes(_) => {
+                open = Some(optimized.len());
+                node
+            }
+            Node::Move(offset) => {
+                position += offset;
+                open = None;
+                node
+            }
+            Node::Input { offset } => {
+                known.remove(&(position + offset));
+                open = None;
+                node
+            }
+            Node::Scan(_) | Node::Loop(_) | Node::If(_) => {
+                // each of these ends on a zero cell, anything else is unknown
+                known.clear();
+                known.insert(0, 0);
+                position = 0;
+                open = None;
+                match node {
+                    Node::Loop(inner) => Node::Loop(Loop {
+                        body: coalesce_output(inner.body),
+                        shift: inner.shift,
+                    }),
+                    Node::If(body) => Node::If(coalesce_output(body)),
+                    node => node,
+                }
+            }
+        };
+        optimized.push(node);
+    }
+
+    optimized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{Optimizer, OptimizerOptions};
+    use crate::parser::Parser;
+
+    fn coalesce(source: &str) -> Vec<Node> {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        optimizer.optimize_with(&OptimizerOptions {
+            output: false,
+            ..OptimizerOptions::default()
+        });
+        coalesce_output(optimizer.program)
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(
+            coalesce(",[-]++.+.[-].")[1..],
+            [
+                Node::Set {
+                    offset: 0,
+                    value: 0
+                },
+                Node::Add {
+                    offset: 0,
+                    delta: 2
+                },
+                Node::OutputBytes(vec![2, 3, 0]),
+                Node::Add {
+                    offset: 0,
+                    delta: 1
+                },
+                Node::Set {
+                    offset: 0,
+                    value: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown() {
+        // the first cell is unknown after the input, and a move ends the
+        // merged output
+        let nodes = coalesce("[-],.[-]+.>[-]+.<.");
+        let outputs: Vec<_> = nodes
+            .iter()
+            .filter(|node| matches!(node, Node::Output { .. } | Node::OutputBytes(_)))
+            .collect();
+        assert_eq!(
+            outputs,
+            [
+                &Node::Output { offset: 0 },
+                &Node::OutputBytes(vec![1]),
+                &Node::OutputBytes(vec![1]),
+                &Node::OutputBytes(vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        // a loop leaves its cell zero, and its body starts with nothing known
+        let nodes = coalesce(",[.[-]+.].");
+        let Node::Loop(inner) = &nodes[1] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(inner.body[0], Node::Output { offset: 0 });
+        assert_eq!(inner.body[3], Node::OutputBytes(vec![1]));
+        assert_eq!(nodes[2], Node::OutputBytes(vec![0]));
+    }
+}
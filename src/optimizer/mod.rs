@@ -1,3 +1,13 @@
+mod coalesce;
+mod offsets;
 mod optimizer;
+mod partial_eval;
+mod peephole;
+mod verify;
 
-pub use self::optimizer::Optimizer;
+pub use self::coalesce::coalesce_output;
+pub use self::offsets::fold_offsets;
+pub use self::optimizer::{Optimizer, OptimizerOptions, Warning, PASSES};
+pub use self::partial_eval::partial_eval;
+pub use self::peephole::peephole;
+pub use self::verify::{verify, VerifyError};
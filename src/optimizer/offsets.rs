@@ -0,0 +1,127 @@
+use crate::ir::{Loop, Node};
+
+/// Folds moves into the offsets of the cell accesses after them, so
+/// `>+>+<<` becomes two `Add`s at offsets 1 and 2 without moving the pointer.
+/// The pointer only moves, by the sum of the folded moves, before a scan, a
+/// loop or an `If`, which work on the current cell, and at the end.
+///
+/// Meant for compiler backends, which address cells at an offset for free.
+/// `Interpreter::compile_ir` has to move to the cell and back instead.
+pub fn fold_offsets(nodes: Vec<Node>) -> Vec<Node> {
+    let mut folded = Vec::with_capacity(nodes.len());
+    let mut pending = 0;
+
+    for node in nodes {
+        let node = match node {
+            Node::Move(n) => {
+                pending += n;
+                continue;
+            }
+            Node::Add { offset, delta } => Node::Add {
+                offset: offset + pending,
+                delta,
+            },
+            Node::Set { offset, value } => Node::Set {
+                offset: offset + pending,
+                value,
+            },
+            Node::Input { offset } => Node::Input {
+                offset: offset + pending,
+            },
+            Node::Output { offset } => Node::Output {
+                offset: offset + pending,
+            },
+            Node::OutputBytes(_) => node,
+            node => {
+                if pending != 0 {
+                    folded.push(Node::Move(pending));
+                    pending = 0;
+                }
+                match node {
+                    Node::Loop(inner) => Node::Loop(Loop::new(fold_offsets(inner.body))),
+                    Node::If(body) => Node::If(fold_offsets(body)),
+                    node => node,
+                }
+            }
+        };
+        folded.push(node);
+    }
+
+    if pending != 0 {
+        folded.push(Node::Move(pending));
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::ir::lower;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn fold(source: &str) -> Vec<Node> {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        fold_offsets(lower(&ast.data))
+    }
+
+    #[test]
+    fn test_fold_offsets() {
+        assert_eq!(
+            fold(">+>-<.,<"),
+            [
+                Node::Add {
+                    offset: 1,
+                    delta: 1
+                },
+                Node::Add {
+                    offset: 2,
+                    delta: -1
+                },
+                Node::Output { offset: 1 },
+                Node::Input { offset: 1 },
+            ]
+        );
+
+        // loops start on their own cell and keep their shift
+        let nodes = fold("[->>+<<]>>[>+<<]");
+        assert_eq!(nodes[1], Node::Move(2));
+        let (Node::Loop(clear), Node::Loop(moving)) = (&nodes[0], &nodes[2]) else {
+            panic!("expected loops");
+        };
+        assert_eq!(
+            clear.body,
+            [
+                Node::Add {
+                    offset: 0,
+                    delta: -1
+                },
+                Node::Add {
+                    offset: 2,
+                    delta: 1
+                },
+            ]
+        );
+        assert!(clear.is_balanced());
+        assert_eq!(moving.shift, Some(-1));
+        assert_eq!(moving.body.last(), Some(&Node::Move(-1)));
+    }
+
+    #[test]
+    fn test_same_output() {
+        let source = "++++++++[>++++++++<-]>+.>+++[<+.>-]<<.";
+        let run = |nodes: &[Node]| {
+            let mut output = Vec::new();
+            Interpreter::builder()
+                .output(&mut output)
+                .build_with_instructions(Interpreter::compile_ir(nodes))
+                .run()
+                .unwrap();
+            output
+        };
+
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        assert_eq!(run(&fold(source)), run(&lower(&ast.data)));
+    }
+}
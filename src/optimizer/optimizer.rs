@@ -1,91 +1,329 @@
+use std::fmt;
 use std::time::Duration;
 
-use crate::cli::Cli;
+use super::{coalesce_output, partial_eval, peephole};
+use crate::analysis::loop_effects;
+use crate::cli::OptimizationArgs;
+use crate::interpreter::{Instruction, Interpreter, DEFAULT_TAPE_SIZE};
+use crate::ir::{self, Node};
 use crate::parser::ASTNode;
+use crate::profiler::loop_source;
 use crate::util::likely;
 
 pub struct Optimizer {
-    pub program: Vec<ASTNode>,
+    pub program: Vec<Node>,
+    /// Loops found while optimizing that may never end
+    pub warnings: Vec<Warning>,
+}
+
+/// A loop that can run forever, with its brainfuck source
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// Never ends once entered, like `[[-]+]`
+    InfiniteLoop(String),
+    /// Never ends when entered on a value that isn't a multiple of the second
+    /// field, like `[--]` on odd values
+    MayNotTerminate(String, u8),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::InfiniteLoop(source) => {
+                write!(f, "loop `{}` never ends once it's entered", source)
+            }
+            Warning::MayNotTerminate(source, step) => write!(
+                f,
+                "loop `{}` never ends when entered on a value that isn't a multiple of {}",
+                source, step
+            ),
+        }
+    }
+}
+
+/// What's known about the tape at a point of the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Known {
+    /// Nothing has been written yet, every cell is zero
+    AllZero,
+    CurrentZero,
+    Unknown,
+}
+
+/// Selects which passes `Optimizer::optimize_with` runs, everything is enabled by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimizerOptions {
+    pub scan: bool,
+    pub clear: bool,
+    pub loops: bool,
+    pub ifs: bool,
+    pub output: bool,
+    pub peephole: bool,
+    pub partial_eval: bool,
+    /// Cells on the tape the program will run with, `partial_eval` stops
+    /// where the pointer would leave it
+    pub tape_size: usize,
+    /// Step limit the program will run with. `partial_eval` is skipped under
+    /// one, the steps it runs at compile time would never count against it
+    pub max_steps: Option<u64>,
+    /// The run saves or resumes a `Snapshot`. `partial_eval` is skipped then
+    /// too, its output depends on `tape_size` and `max_steps`, which would
+    /// make the snapshot's program hash differ between runs
+    pub snapshots: bool,
+}
+
+impl Default for OptimizerOptions {
+    fn default() -> Self {
+        Self {
+            scan: true,
+            clear: true,
+            loops: true,
+            ifs: true,
+            output: true,
+            peephole: true,
+            partial_eval: true,
+            tape_size: DEFAULT_TAPE_SIZE,
+            max_steps: None,
+            snapshots: false,
+        }
+    }
+}
+
+/// Every pass in the order it runs, named like its `--f-no-optimize-*` flag
+pub const PASSES: [&str; 7] = [
+    "scan",
+    "clear",
+    "loops",
+    "ifs",
+    "output",
+    "partial-eval",
+    "peephole",
+];
+
+impl OptimizerOptions {
+    /// Only the first `n` passes of `PASSES` enabled
+    pub fn first(n: usize) -> Self {
+        let enabled = |pass| PASSES.iter().take(n).any(|&name| name == pass);
+        Self {
+            scan: enabled("scan"),
+            clear: enabled("clear"),
+            loops: enabled("loops"),
+            ifs: enabled("ifs"),
+            output: enabled("output"),
+            partial_eval: enabled("partial-eval"),
+            peephole: enabled("peephole"),
+            tape_size: DEFAULT_TAPE_SIZE,
+            max_steps: None,
+            snapshots: false,
+        }
+    }
+}
+
+impl From<&OptimizationArgs> for OptimizerOptions {
+    fn from(args: &OptimizationArgs) -> Self {
+        Self {
+            scan: !args.f_no_optimize_scan,
+            clear: !args.f_no_optimize_clear,
+            loops: !args.f_no_optimize_loops,
+            ifs: !args.f_no_optimize_ifs,
+            output: !args.f_no_optimize_output,
+            peephole: !args.f_no_optimize_peephole,
+            partial_eval: !args.f_no_optimize_partial_eval,
+            tape_size: DEFAULT_TAPE_SIZE,
+            max_steps: None,
+            snapshots: false,
+        }
+    }
 }
 
 impl Optimizer {
-    pub fn new(program: Vec<ASTNode>) -> Self {
-        Self { program }
+    /// Lowers `ast` to the IR the passes work on
+    pub fn new(ast: &[ASTNode]) -> Self {
+        Self {
+            program: ir::lower(ast),
+            warnings: Vec::new(),
+        }
     }
 
-    pub fn optimize(&mut self, cli: &Cli) -> Duration {
+    pub fn optimize(&mut self, args: &OptimizationArgs) -> Duration {
+        self.optimize_with(&OptimizerOptions::from(args))
+    }
+
+    pub fn optimize_with(&mut self, options: &OptimizerOptions) -> Duration {
         let start = std::time::Instant::now();
 
-        if likely(!cli.f_no_optimize_scan) {
+        if likely(options.scan) {
             self.scan_optimization();
         }
-        if likely(!cli.f_no_optimize_clear) {
+        if likely(options.clear) {
             self.clear_optimization();
         }
-        if likely(!cli.f_no_optimize_loops) {
+        if likely(options.loops) {
             self.remove_empty_loops();
         }
+        if likely(options.ifs) {
+            self.if_conversion();
+        }
+        if likely(options.output) {
+            self.program = coalesce_output(std::mem::take(&mut self.program));
+        }
+        self.termination_warnings();
 
         start.elapsed()
     }
 
-    fn remove_empty_loops_helper(nodes: &mut Vec<ASTNode>) {
+    /// Compiles the optimized program to instructions, running `partial_eval`
+    /// and `peephole` on them unless they're disabled, or for `partial_eval`,
+    /// the run has a step limit or snapshots
+    pub fn compile(self, options: &OptimizerOptions) -> Vec<Instruction> {
+        let mut program = Interpreter::compile_ir(&self.program);
+        if likely(options.partial_eval && options.max_steps.is_none() && !options.snapshots) {
+            program = partial_eval(program, options.tape_size);
+        }
+        if likely(options.peephole) {
+            program = peephole(program);
+        }
+        program
+    }
+
+    fn remove_empty_loops_helper(nodes: &mut Vec<Node>) {
         let mut i = 0;
         while i < nodes.len() {
-            match &mut nodes[i] {
-                ASTNode::Loop(inner_nodes) => {
-                    Self::remove_empty_loops_helper(inner_nodes);
-
-                    if inner_nodes.is_empty() {
-                        nodes[i] = ASTNode::NoOp;
-                    } else if inner_nodes.len() == 1 && matches!(inner_nodes[0], ASTNode::Loop(_)) {
-                        nodes[i] = inner_nodes.remove(0);
-                    }
+            if let Node::Loop(inner) = &mut nodes[i] {
+                Self::remove_empty_loops_helper(&mut inner.body);
+
+                if inner.body.is_empty() {
+                    nodes.remove(i);
+                    continue;
+                } else if inner.body.len() == 1 && matches!(inner.body[0], Node::Loop(_)) {
+                    nodes[i] = inner.body.remove(0);
                 }
-                _ => {}
             }
+            i += 1;
+        }
+    }
+
+    /// Removes loops that can't run because their cell is known to be zero,
+    /// like comment loops at the start of a program or a loop right after
+    /// another one
+    fn remove_dead_loops_helper(nodes: &mut Vec<Node>, mut known: Known) {
+        let mut i = 0;
+        while i < nodes.len() {
+            known = match &mut nodes[i] {
+                Node::Loop(_) | Node::If(_) if known != Known::Unknown => {
+                    nodes.remove(i);
+                    continue;
+                }
+                Node::Loop(inner) => {
+                    Self::remove_dead_loops_helper(&mut inner.body, Known::Unknown);
+                    // a removed loop may have been what made the shift unknown
+                    *inner = ir::Loop::new(std::mem::take(&mut inner.body));
+                    Known::CurrentZero
+                }
+                Node::If(body) => {
+                    Self::remove_dead_loops_helper(body, Known::Unknown);
+                    Known::CurrentZero
+                }
+                Node::Move(_) if known == Known::CurrentZero => Known::Unknown,
+                Node::Set {
+                    offset: 0,
+                    value: 0,
+                } if known == Known::Unknown => Known::CurrentZero,
+                Node::Set {
+                    offset: 0,
+                    value: 0,
+                } => known,
+                Node::Add { offset: 0, .. }
+                | Node::Set { offset: 0, .. }
+                | Node::Input { offset: 0 } => Known::Unknown,
+                // writes to other cells leave the current one alone
+                Node::Add { .. } | Node::Set { .. } | Node::Input { .. }
+                    if known == Known::AllZero =>
+                {
+                    Known::CurrentZero
+                }
+                // scans stop on a zero cell and don't move on one
+                Node::Scan(_) if known == Known::Unknown => Known::CurrentZero,
+                _ => known,
+            };
+            i += 1;
+        }
+    }
 
-            if matches!(nodes[i], ASTNode::NoOp) {
-                nodes.remove(i);
-            } else {
-                i += 1;
+    /// Turns loops that always end on a zeroed loop cell into `Node::If`s
+    fn if_conversion_helper(nodes: &mut [Node]) {
+        for node in nodes.iter_mut() {
+            if let Node::Loop(ir::Loop { body, .. }) | Node::If(body) = node {
+                Self::if_conversion_helper(body);
+            }
+            if let Node::Loop(inner) = node {
+                if zeroes_loop_cell(&inner.body) {
+                    *node = Node::If(std::mem::take(&mut inner.body));
+                }
             }
         }
     }
 
-    fn clear_optimization_helper(nodes: &mut Vec<ASTNode>) {
+    /// Replaces loops that only change their own cell and always end with
+    /// it zero by a `Set` of 0
+    fn clear_optimization_helper(nodes: &mut [Node]) {
+        const CLEAR: Node = Node::Set {
+            offset: 0,
+            value: 0,
+        };
+
         for node in nodes.iter_mut() {
-            match node {
-                ASTNode::Loop(inner_nodes) => match inner_nodes.as_slice() {
-                    [ASTNode::Decr(1)] => *node = ASTNode::Set(0),
-                    _ => Self::clear_optimization_helper(inner_nodes),
-                },
+            let Node::Loop(inner) = node else {
+                continue;
+            };
+            // nested clears become `Set`s first, `[-[-]]` is a clear too
+            Self::clear_optimization_helper(&mut inner.body);
+
+            match clear_step(&inner.body) {
+                Some(Step::Set(0)) => *node = CLEAR,
+                // an odd step reaches zero from any value, wrapping around
+                Some(Step::Add(delta)) if delta % 2 == 1 => *node = CLEAR,
                 _ => {}
             }
         }
     }
 
-    fn scan_optimization_helper(nodes: &mut Vec<ASTNode>) {
-        for node in nodes.iter_mut() {
-            match node {
-                ASTNode::Loop(inner_nodes) => {
-                    if inner_nodes.len() == 1 {
-                        match &inner_nodes[0] {
-                            ASTNode::Next(1) => *node = ASTNode::ScanRight,
-                            ASTNode::Prev(1) => *node = ASTNode::ScanLeft,
-                            _ => {}
-                        }
-                    } else {
-                        Self::scan_optimization_helper(inner_nodes);
-                    }
+    /// Warns about the clear-like loops `clear_optimization_helper` left
+    /// alone because they may not end
+    fn termination_warnings_helper(nodes: &[Node], warnings: &mut Vec<Warning>) {
+        for node in nodes {
+            let (Node::Loop(ir::Loop { body, .. }) | Node::If(body)) = node else {
+                continue;
+            };
+            Self::termination_warnings_helper(body, warnings);
+
+            let source = || loop_source(&Interpreter::compile_ir(std::slice::from_ref(node)));
+            match clear_step(body) {
+                Some(Step::Set(1..) | Step::Add(0)) => {
+                    warnings.push(Warning::InfiniteLoop(source()))
                 }
+                Some(Step::Add(delta)) if delta % 2 == 0 => warnings.push(
+                    Warning::MayNotTerminate(source(), 1 << delta.trailing_zeros()),
+                ),
                 _ => {}
             }
         }
     }
 
+    fn scan_optimization_helper(nodes: &mut [Node]) {
+        for node in nodes.iter_mut() {
+            if let Node::Loop(inner) = node {
+                match inner.body[..] {
+                    [Node::Move(step @ (1 | -1))] => *node = Node::Scan(step),
+                    _ => Self::scan_optimization_helper(&mut inner.body),
+                }
+            }
+        }
+    }
+
     pub fn remove_empty_loops(&mut self) {
         Self::remove_empty_loops_helper(&mut self.program);
+        Self::remove_dead_loops_helper(&mut self.program, Known::AllZero);
     }
 
     pub fn scan_optimization(&mut self) {
@@ -95,6 +333,75 @@ impl Optimizer {
     pub fn clear_optimization(&mut self) {
         Self::clear_optimization_helper(&mut self.program);
     }
+
+    /// Collects `warnings`, after dead loops are gone so comment loops don't
+    /// count
+    pub fn termination_warnings(&mut self) {
+        self.warnings.clear();
+        Self::termination_warnings_helper(&self.program, &mut self.warnings);
+    }
+
+    pub fn if_conversion(&mut self) {
+        Self::if_conversion_helper(&mut self.program);
+    }
+}
+
+/// What one iteration of a clear-like loop does to its cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    /// Leaves this value whatever the cell was
+    Set(u8),
+    /// Adds this amount, wrapping
+    Add(u8),
+}
+
+/// What one iteration of a loop does to its cell, if the body only ever
+/// changes that cell, like `[-]`, `[+++]` or `[-[-]]` once its inner clear
+/// became a `Set`
+fn clear_step(body: &[Node]) -> Option<Step> {
+    body.iter().try_fold(Step::Add(0), |step, node| {
+        let n = match *node {
+            Node::Add { offset: 0, delta } => delta as u8,
+            Node::Set { offset: 0, value } => return Some(Step::Set(value)),
+            _ => return None,
+        };
+        Some(match step {
+            Step::Set(value) => Step::Set(value.wrapping_add(n)),
+            Step::Add(delta) => Step::Add(delta.wrapping_add(n)),
+        })
+    })
+}
+
+/// Whether `body` always ends on the cell it started on with that cell zero,
+/// e.g. `>+<[-]` or `>+<[->+<]`, so a loop around it never repeats
+fn zeroes_loop_cell(body: &[Node]) -> bool {
+    let mut position = 0isize;
+    let mut zeroed = false;
+
+    for node in body {
+        let cell = position + node.offset();
+        match node {
+            Node::Move(n) => position += n,
+            Node::Set { value, .. } if cell == 0 => zeroed = *value == 0,
+            Node::Add { .. } | Node::Input { .. } if cell == 0 => zeroed = false,
+            Node::Loop(ir::Loop { body: inner, .. }) | Node::If(inner) => {
+                let effects = loop_effects(inner);
+                if !effects.is_balanced() || effects.unknown_cells {
+                    return false;
+                }
+                // a loop always leaves its own cell zero
+                if position == 0 {
+                    zeroed = true;
+                } else if effects.writes.contains(&-position) {
+                    zeroed = false;
+                }
+            }
+            Node::Scan(_) => return false,
+            _ => {}
+        }
+    }
+
+    position == 0 && zeroed
 }
 
 #[cfg(test)]
@@ -103,9 +410,29 @@ mod tests {
 
     use super::*;
 
+    fn add(delta: i8) -> Node {
+        Node::Add { offset: 0, delta }
+    }
+
+    fn set(value: u8) -> Node {
+        Node::Set { offset: 0, value }
+    }
+
+    fn looped(body: Vec<Node>) -> Node {
+        Node::Loop(ir::Loop::new(body))
+    }
+
+    fn optimize(source: &str) -> Vec<Node> {
+        let lexer = crate::lexer::Lexer::new(String::from(source));
+        let ast = crate::parser::Parser::new(lexer).parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        optimizer.optimize_with(&OptimizerOptions::default());
+        optimizer.program
+    }
+
     #[test]
     fn test_scan_right_left() {
-        let mut optimizer = Optimizer::new(vec![ASTNode::Loop(vec![
+        let mut optimizer = Optimizer::new(&[ASTNode::Loop(vec![
             ASTNode::Incr(1),
             ASTNode::Next(1),
             ASTNode::Incr(1),
@@ -120,22 +447,22 @@ mod tests {
 
         assert_eq!(
             optimizer.program,
-            vec![ASTNode::Loop(vec![
-                ASTNode::Incr(1),
-                ASTNode::Next(1),
-                ASTNode::Incr(1),
-                ASTNode::Next(1),
-                ASTNode::Incr(1),
-                ASTNode::Prev(2),
-                ASTNode::ScanRight,
-                ASTNode::ScanLeft,
+            vec![looped(vec![
+                add(1),
+                Node::Move(1),
+                add(1),
+                Node::Move(1),
+                add(1),
+                Node::Move(-2),
+                Node::Scan(1),
+                Node::Scan(-1),
             ])]
         );
     }
 
     #[test]
-    fn test_optimized_ast_nested() {
-        let mut optimizer = Optimizer::new(vec![ASTNode::Loop(vec![
+    fn test_scan_nested() {
+        let mut optimizer = Optimizer::new(&[ASTNode::Loop(vec![
             ASTNode::Incr(100),
             ASTNode::Loop(vec![ASTNode::Next(1)]),
             ASTNode::Loop(vec![
@@ -143,18 +470,19 @@ mod tests {
                 ASTNode::Loop(vec![ASTNode::Next(1)]),
                 ASTNode::Prev(1),
             ]),
+            ASTNode::Loop(vec![ASTNode::Next(2)]),
         ])]);
 
         optimizer.scan_optimization();
 
-        println!("{:?}", optimizer.program);
-
         assert_eq!(
             optimizer.program,
-            vec![ASTNode::Loop(vec![
-                ASTNode::Incr(100),
-                ASTNode::ScanRight,
-                ASTNode::Loop(vec![ASTNode::Next(1), ASTNode::ScanRight, ASTNode::Prev(1),]),
+            vec![looped(vec![
+                add(100),
+                Node::Scan(1),
+                looped(vec![Node::Move(1), Node::Scan(1), Node::Move(-1)]),
+                // only single steps have a scan instruction
+                looped(vec![Node::Move(2)]),
             ])]
         );
     }
@@ -164,34 +492,219 @@ mod tests {
      */
     #[test]
     fn simplify_nested_loops() {
-        let mut optimizer = Optimizer::new(vec![ASTNode::Loop(vec![
+        let mut optimizer = Optimizer::new(&[ASTNode::Loop(vec![
             ASTNode::Loop(vec![ASTNode::Loop(vec![])]),
             ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Incr(1)])]),
         ])]);
 
         Optimizer::remove_empty_loops_helper(&mut optimizer.program);
 
-        assert_eq!(
-            optimizer.program,
-            vec![ASTNode::Loop(vec![ASTNode::Incr(1)])]
-        );
+        assert_eq!(optimizer.program, vec![looped(vec![add(1)])]);
     }
 
     #[test]
     fn remove_unused_loops() {
-        let mut optimizer = Optimizer::new(vec![ASTNode::Loop(vec![ASTNode::Loop(vec![
-            ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Loop(vec![
-                ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Loop(vec![
-                    ASTNode::Loop(vec![ASTNode::Incr(1)]),
-                ])])]),
-            ])])]),
-        ])])]);
+        let mut optimizer =
+            Optimizer::new(&[ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Loop(
+                vec![ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Loop(
+                    vec![ASTNode::Loop(vec![ASTNode::Loop(vec![ASTNode::Loop(
+                        vec![ASTNode::Incr(1)],
+                    )])])],
+                )])])],
+            )])])]);
 
         Optimizer::remove_empty_loops_helper(&mut optimizer.program);
 
+        assert_eq!(optimizer.program, vec![looped(vec![add(1)])]);
+    }
+
+    #[test]
+    fn remove_dead_loops() {
+        // a comment loop at the start, even after moving the pointer
+        assert_eq!(optimize("[comment]>>[.]+"), vec![Node::Move(2), add(1)]);
+        // a loop right after another one, after a clear and inside a body
         assert_eq!(
-            optimizer.program,
-            vec![ASTNode::Loop(vec![ASTNode::Incr(1)]),]
+            optimize(",[>[-][.]<[>]-][.]"),
+            vec![
+                Node::Input { offset: 0 },
+                looped(vec![
+                    Node::Move(1),
+                    set(0),
+                    Node::Move(-1),
+                    Node::Scan(1),
+                    add(-1),
+                ]),
+            ]
+        );
+        // a written cell, or any cell after moving away from one, may be nonzero
+        assert_eq!(
+            optimize("+[.]>[.]"),
+            vec![
+                add(1),
+                looped(vec![Node::Output { offset: 0 }]),
+                Node::Move(1),
+                looped(vec![Node::Output { offset: 0 }]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dead_loop_shift() {
+        // the inner loop that moved the pointer can't run after the clear
+        let program = optimize(",[>[-][>>]<-]");
+        let Node::Loop(outer) = &program[1] else {
+            panic!("expected a loop");
+        };
+        assert!(outer.is_balanced());
+    }
+
+    #[test]
+    fn if_conversion() {
+        // cleared last, or by a nested loop on the loop cell
+        assert_eq!(
+            optimize(",[>+<[-]]"),
+            vec![
+                Node::Input { offset: 0 },
+                Node::If(vec![Node::Move(1), add(1), Node::Move(-1), set(0)]),
+            ]
+        );
+        assert!(matches!(optimize(",[>+<[->+<]]")[1], Node::If(_)));
+
+        // only the inner loop always ends on zero
+        assert_eq!(
+            optimize(",[>,[<+>[-]]<-]"),
+            vec![
+                Node::Input { offset: 0 },
+                looped(vec![
+                    Node::Move(1),
+                    Node::Input { offset: 0 },
+                    Node::If(vec![Node::Move(-1), add(1), Node::Move(1), set(0)]),
+                    Node::Move(-1),
+                    add(-1),
+                ]),
+            ]
+        );
+
+        // written after the clear, ending elsewhere, written by a nested
+        // loop or moved by an unknown amount
+        for source in [",[[-]+]", ",[[-]>]", ",[[-]>[<+>-]<]", ",[[-]>[<]]"] {
+            assert!(matches!(optimize(source)[1], Node::Loop(_)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        // cells away from the pointer count as the cells they address
+        assert!(zeroes_loop_cell(&[
+            Node::Add {
+                offset: 1,
+                delta: 1
+            },
+            Node::Set {
+                offset: 0,
+                value: 0
+            },
+        ]));
+        assert!(!zeroes_loop_cell(&[
+            Node::Move(1),
+            Node::Set {
+                offset: -1,
+                value: 0
+            },
+            Node::Add {
+                offset: -1,
+                delta: 1
+            },
+            Node::Move(-1),
+        ]));
+        assert_eq!(
+            clear_step(&[Node::Add {
+                offset: 1,
+                delta: 1
+            }]),
+            None
+        );
+    }
+
+    #[test]
+    fn clear_loops() {
+        let clear = |source: &str| {
+            let lexer = crate::lexer::Lexer::new(String::from(source));
+            let ast = crate::parser::Parser::new(lexer).parse_helper();
+            let mut optimizer = Optimizer::new(&ast.data);
+            optimizer.clear_optimization();
+            optimizer.termination_warnings();
+            (optimizer.program, optimizer.warnings)
+        };
+
+        // odd steps wrap around to zero from any value, and so does a body
+        // that ends with its cell cleared
+        for source in [
+            "[-]", "[+]", "[---]", "[+++++]", "[-[-]]", "[+[+]]", "[-ab]",
+        ] {
+            assert_eq!(clear(source), (vec![set(0)], vec![]), "{}", source);
+        }
+
+        // even steps skip odd values forever, so they stay loops
+        let (program, warnings) = clear("[--][++++][-+]");
+        assert!(program.iter().all(|node| matches!(node, Node::Loop(_))));
+        assert_eq!(
+            warnings,
+            [
+                Warning::MayNotTerminate(String::from("[--]"), 2),
+                Warning::MayNotTerminate(String::from("[++++]"), 4),
+                Warning::InfiniteLoop(String::from("[-+]")),
+            ]
         );
+
+        // ends on a nonzero value, or touches other cells
+        let (program, warnings) = clear("[[-]+][->+<]");
+        assert!(matches!(program[..], [Node::Loop(_), Node::Loop(_)]));
+        assert_eq!(warnings, [Warning::InfiniteLoop(String::from("[[-]+]"))]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let source = "++++++++[>++++++++<-]>+.+.+.";
+        let compile = |options: &OptimizerOptions| {
+            let lexer = crate::lexer::Lexer::new(String::from(source));
+            let ast = crate::parser::Parser::new(lexer).parse_helper();
+            let mut optimizer = Optimizer::new(&ast.data);
+            optimizer.optimize_with(options);
+            optimizer.compile(options)
+        };
+
+        // saved under a step limit, then resumed without one, with every
+        // pass enabled
+        let options = OptimizerOptions {
+            max_steps: Some(20),
+            snapshots: true,
+            ..OptimizerOptions::default()
+        };
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::builder()
+            .output(&mut output)
+            .max_steps(20)
+            .build_with_instructions(compile(&options));
+        assert!(interpreter.run().is_err());
+        let mut saved = Vec::new();
+        interpreter.snapshot().write_to(&mut saved).unwrap();
+        drop(interpreter);
+
+        let options = OptimizerOptions {
+            snapshots: true,
+            ..OptimizerOptions::default()
+        };
+        let snapshot = crate::interpreter::Snapshot::read_from(&mut &saved[..]).unwrap();
+        let mut rest = Vec::new();
+        let mut interpreter = Interpreter::builder()
+            .output(&mut rest)
+            .build_with_instructions(compile(&options));
+        interpreter.restore(&snapshot).unwrap();
+        interpreter.run().unwrap();
+        drop(interpreter);
+
+        output.extend(rest);
+        assert_eq!(output, b"ABC");
     }
 }
@@ -0,0 +1,219 @@
+use crate::interpreter::Instruction;
+
+/// Most instructions `partial_eval` runs before giving up on the rest
+const STEP_BUDGET: u64 = 1 << 20;
+
+/// Tape, pointer and output at the start of a top-level instruction
+#[derive(Debug, Clone, Default)]
+struct State {
+    program_counter: usize,
+    tape: Vec<u8>,
+    pointer: usize,
+    output: Vec<u8>,
+}
+
+/// Runs the program from the all-zero tape until its first `Input` or until
+/// `STEP_BUDGET` runs out, and replaces the part that ran with its output as
+/// one `OutputBytes` followed by the tape it left behind.
+///
+/// Only whole top-level instructions are replaced, so a loop that was still
+/// running when evaluation stopped is left in place with everything after it.
+/// Expects instructions straight from `Interpreter::compile`, and the pointer
+/// never leaves the first `tape_size` cells, which errors are left to run time.
+pub fn partial_eval(program: Vec<Instruction>, tape_size: usize) -> Vec<Instruction> {
+    let state = evaluate(&program, tape_size);
+    if state.program_counter == 0 {
+        return program;
+    }
+
+    let mut optimized = Vec::new();
+    if !state.output.is_empty() {
+        optimized.push(Instruction::OutputBytes(state.output));
+    }
+
+    let mut pointer = 0;
+    for (cell, &value) in state.tape.iter().enumerate() {
+        if value != 0 {
+            optimized.extend(move_by(pointer, cell));
+            optimized.push(Instruction::Set(value));
+            pointer = cell;
+        }
+    }
+    optimized.extend(move_by(pointer, state.pointer));
+
+    optimized.extend_from_slice(&program[state.program_counter..]);
+    optimized
+}
+
+fn move_by(from: usize, to: usize) -> Option<Instruction> {
+    match to.cmp(&from) {
+        std::cmp::Ordering::Greater => Some(Instruction::Next(to - from)),
+        std::cmp::Ordering::Less => Some(Instruction::Prev(from - to)),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// State at the last top-level instruction that can safely be resumed from
+fn evaluate(program: &[Instruction], tape_size: usize) -> State {
+    let mut state = State::default();
+    // state before the top-level loop that's running, if any
+    let mut checkpoint: Option<State> = None;
+    let mut depth = 0;
+    let mut steps = 0;
+
+    while state.program_counter < program.len() {
+        steps += 1;
+        if steps > STEP_BUDGET {
+            break;
+        }
+
+        let pc = state.program_counter;
+        if let Instruction::BeginLoop(_) = program[pc] {
+            if depth == 0 {
+                checkpoint = Some(state.clone());
+            }
+        }
+        if state.pointer >= state.tape.len() {
+            state.tape.resize(state.pointer + 1, 0);
+        }
+        let cell = &mut state.tape[state.pointer];
+
+        state.program_counter += 1;
+        match program[pc] {
+            Instruction::Incr(n) => *cell = cell.wrapping_add(n),
+            Instruction::Decr(n) => *cell = cell.wrapping_sub(n),
+            Instruction::Set(n) => *cell = n,
+            Instruction::Output => state.output.push(*cell),
+            Instruction::OutputBytes(ref bytes) => state.output.extend_from_slice(bytes),
+            Instruction::Next(n) if state.pointer + n < tape_size => state.pointer += n,
+            Instruction::Prev(n) if n <= state.pointer => state.pointer -= n,
+            Instruction::BeginLoop(offset) => match *cell {
+                0 => state.program_counter += offset,
+                _ => depth += 1,
+            },
+            Instruction::EndLoop(offset) => match *cell {
+                0 => depth -= 1,
+                _ => state.program_counter -= offset,
+            },
+            // the rest of a body can be resumed from, it runs straight through
+            Instruction::If(offset) if *cell == 0 => state.program_counter += offset,
+            Instruction::If(_) => {}
+            Instruction::ScanRight | Instruction::ScanLeft if *cell == 0 => {}
+            Instruction::ScanRight => {
+                // cells past the end of `tape` are zero
+                let distance = state.tape[state.pointer..]
+                    .iter()
+                    .position(|&cell| cell == 0);
+                state.pointer += distance.unwrap_or(state.tape.len() - state.pointer);
+                if state.pointer >= tape_size {
+                    state.program_counter = pc;
+                    break;
+                }
+            }
+            Instruction::ScanLeft => {
+                match state.tape[..state.pointer]
+                    .iter()
+                    .rposition(|&cell| cell == 0)
+                {
+                    Some(cell) => state.pointer = cell,
+                    None => {
+                        state.program_counter = pc;
+                        break;
+                    }
+                }
+            }
+            // input and pointer errors are left to run time
+            _ => {
+                state.program_counter = pc;
+                break;
+            }
+        }
+    }
+
+    match depth {
+        0 => state,
+        _ => checkpoint.expect("loops start at the top level"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, DEFAULT_TAPE_SIZE};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> Vec<Instruction> {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        Interpreter::compile(ast.data)
+    }
+
+    #[test]
+    fn test_whole_program() {
+        // prints "AB" and leaves 66 in cell 1
+        let program = partial_eval(compile("++++++++[>++++++++<-]>+.+.>"), DEFAULT_TAPE_SIZE);
+        assert_eq!(
+            program,
+            [
+                Instruction::OutputBytes(b"AB".to_vec()),
+                Instruction::Next(1),
+                Instruction::Set(66),
+                Instruction::Next(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        let program = partial_eval(compile("+++.>++,[.,]"), DEFAULT_TAPE_SIZE);
+        assert_eq!(
+            program,
+            [
+                Instruction::OutputBytes(vec![3]),
+                Instruction::Set(3),
+                Instruction::Next(1),
+                Instruction::Set(2),
+                Instruction::Input,
+                Instruction::BeginLoop(3),
+                Instruction::Output,
+                Instruction::Input,
+                Instruction::EndLoop(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_with_input() {
+        // the loop reads input, so it stays
+        let program = partial_eval(compile("+.++[-.>,<]"), DEFAULT_TAPE_SIZE);
+        assert_eq!(
+            program[..3],
+            [
+                Instruction::OutputBytes(vec![1]),
+                Instruction::Set(3),
+                Instruction::BeginLoop(6),
+            ]
+        );
+
+        let source = "+[]";
+        assert_eq!(
+            partial_eval(compile(source), DEFAULT_TAPE_SIZE),
+            [
+                Instruction::Set(1),
+                Instruction::BeginLoop(1),
+                Instruction::EndLoop(1),
+            ]
+        );
+        // nothing to evaluate before an input or an error
+        assert_eq!(
+            partial_eval(compile(",+"), DEFAULT_TAPE_SIZE),
+            compile(",+")
+        );
+        assert_eq!(
+            partial_eval(compile("<+"), DEFAULT_TAPE_SIZE),
+            compile("<+")
+        );
+        // including a move past the end of a small tape
+        assert_eq!(partial_eval(compile(">>>>."), 4), compile(">>>>."));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::interpreter::Instruction;
+
+/// Folds and fuses neighbouring instructions of a compiled program, then
+/// recomputes every jump offset.
+///
+/// The IR passes only look inside one loop at a time, this pass also sees
+/// pairs that cross loop boundaries. The superinstructions are the pairs that
+/// were executed most in `fucker --profile` runs of `examples/`, e.g. `>]` and
+/// `<]` make up a quarter of mandel.b and golden_short.b, `-]` and `[-]>` a
+/// sixth of hanoi.b.
+pub fn peephole(program: Vec<Instruction>) -> Vec<Instruction> {
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(program.len());
+    // new index and old target of every `If` whose body hasn't ended yet
+    let mut open_ifs: Vec<(usize, usize)> = Vec::new();
+    // instructions before this index are never combined with later ones
+    let mut barrier = 0;
+
+    for (index, instruction) in program.into_iter().enumerate() {
+        // the end of an `If` body is a jump target
+        while let Some(&(start, _)) = open_ifs.last().filter(|&&(_, end)| end == index) {
+            optimized[start] = Instruction::If(optimized.len() - start - 1);
+            open_ifs.pop();
+            barrier = optimized.len();
+        }
+        if let Instruction::If(offset) = instruction {
+            open_ifs.push((optimized.len(), index + offset + 1));
+        }
+
+        let mut current = Some(instruction);
+        while let Some(instruction) = current.take() {
+            match optimized
+                .last()
+                .filter(|_| optimized.len() > barrier)
+                .and_then(|last| combine(last, &instruction))
+            {
+                Some(combined) => {
+                    optimized.pop();
+                    // the combined instruction may fold into the one before,
+                    // a `None` result means the pair cancelled out
+                    current = combined;
+                }
+                None => optimized.push(instruction),
+            }
+        }
+    }
+
+    for (start, _) in open_ifs {
+        optimized[start] = Instruction::If(optimized.len() - start - 1);
+    }
+    fix_jumps(&mut optimized);
+    optimized
+}
+
+/// Replacement for `last` followed by `next`: `Some(None)` when the pair has
+/// no effect, `None` when it can't be combined. Jump targets are right after
+/// a loop instruction, which is never `last` here, or at the end of an `If`
+/// body, which `peephole` doesn't combine across, so no jump lands between
+/// the two.
+fn combine(last: &Instruction, next: &Instruction) -> Option<Option<Instruction>> {
+    let combined = match (last, next) {
+        (
+            &Instruction::Incr(a) | &Instruction::Decr(a),
+            Instruction::Incr(_) | Instruction::Decr(_),
+        ) => {
+            let delta = match (last, next) {
+                (Instruction::Incr(_), &Instruction::Incr(b)) => a.wrapping_add(b),
+                (Instruction::Incr(_), &Instruction::Decr(b)) => a.wrapping_sub(b),
+                (Instruction::Decr(_), &Instruction::Incr(b)) => b.wrapping_sub(a),
+                (_, &Instruction::Decr(b)) => a.wrapping_add(b).wrapping_neg(),
+                _ => unreachable!(),
+            };
+            return Some(match delta {
+                0 => None,
+                1..=127 => Some(Instruction::Incr(delta)),
+                _ => Some(Instruction::Decr(delta.wrapping_neg())),
+            });
+        }
+        (&Instruction::Set(a), &Instruction::Incr(b)) => Instruction::Set(a.wrapping_add(b)),
+        (&Instruction::Set(a), &Instruction::Decr(b)) => Instruction::Set(a.wrapping_sub(b)),
+        (
+            Instruction::Set(_) | Instruction::Incr(_) | Instruction::Decr(_),
+            &Instruction::Set(b),
+        ) => Instruction::Set(b),
+        (&Instruction::Set(a), &Instruction::Next(b)) => Instruction::SetNext(a, b),
+        (&Instruction::Set(a), &Instruction::Prev(b)) => Instruction::SetPrev(a, b),
+        (&Instruction::Next(a), &Instruction::EndLoop(offset)) => {
+            Instruction::NextEndLoop(a, offset)
+        }
+        (&Instruction::Prev(a), &Instruction::EndLoop(offset)) => {
+            Instruction::PrevEndLoop(a, offset)
+        }
+        (&Instruction::Decr(a), &Instruction::EndLoop(offset)) => {
+            Instruction::DecrEndLoop(a, offset)
+        }
+        (&Instruction::Next(a), &Instruction::ScanRight) => Instruction::NextScanRight(a),
+        (&Instruction::Prev(a), &Instruction::ScanLeft) => Instruction::PrevScanLeft(a),
+        _ => return None,
+    };
+    Some(Some(combined))
+}
+
+/// Points every `BeginLoop` and loop end at its partner again
+fn fix_jumps(program: &mut [Instruction]) {
+    let mut open = Vec::new();
+    for index in 0..program.len() {
+        match program[index] {
+            Instruction::BeginLoop(_) => open.push(index),
+            ref instruction => {
+                let Some(_) = instruction.end_loop() else {
+                    continue;
+                };
+                let start = open.pop().expect("unbalanced loop");
+                let offset = index - start;
+
+                program[start] = Instruction::BeginLoop(offset);
+                program[index] = match program[index] {
+                    Instruction::NextEndLoop(n, _) => Instruction::NextEndLoop(n, offset),
+                    Instruction::PrevEndLoop(n, _) => Instruction::PrevEndLoop(n, offset),
+                    Instruction::DecrEndLoop(n, _) => Instruction::DecrEndLoop(n, offset),
+                    _ => Instruction::EndLoop(offset),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{Optimizer, OptimizerOptions};
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> Vec<Instruction> {
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        optimizer.optimize_with(&OptimizerOptions::default());
+        Interpreter::compile_ir(&optimizer.program)
+    }
+
+    #[test]
+    fn test_superinstructions() {
+        assert_eq!(
+            peephole(compile("+[->>+<<][-]+++>[>]<[<]>+<[<<]")),
+            [
+                Instruction::Incr(1),
+                Instruction::BeginLoop(4),
+                Instruction::Decr(1),
+                Instruction::Next(2),
+                Instruction::Incr(1),
+                Instruction::PrevEndLoop(2, 4),
+                Instruction::SetNext(3, 1),
+                Instruction::ScanRight,
+                Instruction::PrevScanLeft(1),
+                Instruction::Next(1),
+                Instruction::Incr(1),
+                Instruction::Prev(1),
+                Instruction::BeginLoop(1),
+                Instruction::PrevEndLoop(2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_folds() {
+        // `Set(0)` followed by `+++` is `Set(3)`, `+-` cancels out and lets
+        // the `-` before it end the loop
+        assert_eq!(
+            peephole(compile("+[>+<-+-]++--")),
+            [
+                Instruction::Incr(1),
+                Instruction::BeginLoop(4),
+                Instruction::Next(1),
+                Instruction::Incr(1),
+                Instruction::Prev(1),
+                Instruction::DecrEndLoop(1, 4),
+            ]
+        );
+        assert_eq!(peephole(compile("[-]+++")), [Instruction::Set(3)]);
+    }
+
+    #[test]
+    fn test_nested_jumps() {
+        let program = peephole(compile("++[>++[>+<-]<-]>>."));
+        assert_eq!(
+            program,
+            [
+                Instruction::Incr(2),
+                Instruction::BeginLoop(9),
+                Instruction::Next(1),
+                Instruction::Incr(2),
+                Instruction::BeginLoop(4),
+                Instruction::Next(1),
+                Instruction::Incr(1),
+                Instruction::Prev(1),
+                Instruction::DecrEndLoop(1, 4),
+                Instruction::Prev(1),
+                Instruction::DecrEndLoop(1, 9),
+                Instruction::Next(2),
+                Instruction::Output,
+            ]
+        );
+
+        let mut output = Vec::new();
+        Interpreter::builder()
+            .output(&mut output)
+            .build_with_instructions(program)
+            .run()
+            .unwrap();
+        assert_eq!(output, [4]);
+    }
+
+    #[test]
+    fn test_if() {
+        // the body shrinks, and its final `Set(0)` isn't folded into the `+`
+        // that the `If` jumps to
+        let program = peephole(compile(",[>++-<[-]]+."));
+        assert_eq!(
+            program,
+            [
+                Instruction::Input,
+                Instruction::If(4),
+                Instruction::Next(1),
+                Instruction::Incr(1),
+                Instruction::Prev(1),
+                Instruction::Set(0),
+                Instruction::Incr(1),
+                Instruction::OutputBytes(vec![1]),
+            ]
+        );
+
+        for input in [0, 5] {
+            let mut output = Vec::new();
+            Interpreter::builder()
+                .input(&[input][..])
+                .output(&mut output)
+                .build_with_instructions(program.clone())
+                .run()
+                .unwrap();
+            assert_eq!(output, [1]);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use super::{Optimizer, OptimizerOptions, PASSES};
+use crate::interpreter::{self, Interpreter};
+use crate::parser::ASTNode;
+
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The unoptimized program stopped on something other than the end, a
+    /// move off the tape or the step limit, so there's nothing to compare
+    Unfinished(interpreter::Error),
+    /// Enabling `pass` on top of the ones before it changed `what`
+    Diverged {
+        pass: &'static str,
+        what: &'static str,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Unfinished(err) => {
+                write!(
+                    f,
+                    "Can't verify optimizations, the unoptimized program stopped: {}",
+                    err
+                )
+            }
+            VerifyError::Diverged { pass, what } => {
+                write!(f, "Optimization `{}` changed the program's {}", pass, what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Passes that don't change how many instructions run, so even a run the
+/// step limit cut short must match the run without them
+const STEP_NEUTRAL: [&str; 1] = ["partial-eval"];
+
+/// Everything `verify` compares at the end of a run
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: Vec<u8>,
+    memory: Vec<u8>,
+    pointer: usize,
+    error: Option<String>,
+}
+
+/// Runs the program with the first `passes` of `PASSES` enabled
+fn run(
+    ast: &[ASTNode],
+    passes: usize,
+    input: &[u8],
+    max_steps: Option<u64>,
+    tape_size: usize,
+) -> (Outcome, Option<interpreter::Error>) {
+    let options = OptimizerOptions {
+        tape_size,
+        max_steps,
+        ..OptimizerOptions::first(passes)
+    };
+    let mut optimizer = Optimizer::new(ast);
+    optimizer.optimize_with(&options);
+    let program = optimizer.compile(&options);
+
+    let mut output = Vec::new();
+    let mut builder = Interpreter::builder()
+        .input(input)
+        .output(&mut output)
+        .tape_size(tape_size);
+    if let Some(max_steps) = max_steps {
+        builder = builder.max_steps(max_steps);
+    }
+    let mut interpreter = builder.build_with_instructions(program);
+    let result = interpreter.run();
+    let memory = interpreter.memory().to_vec();
+    let pointer = interpreter.memory_pointer();
+    drop(interpreter);
+
+    let outcome = Outcome {
+        output,
+        memory,
+        pointer,
+        error: result.as_ref().err().map(ToString::to_string),
+    };
+    (outcome, result.err())
+}
+
+/// Runs the program without optimizations, then with the passes in `PASSES`
+/// enabled one after another, all on the same input, and returns the output
+/// of the fully optimized run if output, tape and pointer always match.
+///
+/// Most passes change how many instructions run, so when the step limit stops
+/// the unoptimized program, the others only have to print the same bytes as
+/// far as both got, except for `STEP_NEUTRAL` passes, which must match the run
+/// before them exactly.
+pub fn verify(
+    ast: &[ASTNode],
+    input: &[u8],
+    max_steps: Option<u64>,
+    tape_size: usize,
+) -> Result<Vec<u8>, VerifyError> {
+    let (expected, error) = run(ast, 0, input, max_steps, tape_size);
+    let limited = matches!(error, Some(interpreter::Error::StepLimitExceeded));
+    if let Some(err) = error {
+        if !matches!(
+            err,
+            interpreter::Error::PointerOutOfBounds | interpreter::Error::StepLimitExceeded
+        ) {
+            return Err(VerifyError::Unfinished(err));
+        }
+    }
+
+    let mut previous = expected;
+    for (index, &pass) in PASSES.iter().enumerate() {
+        let (actual, _) = run(ast, index + 1, input, max_steps, tape_size);
+
+        let what = if limited && !STEP_NEUTRAL.contains(&pass) {
+            let len = actual.output.len().min(previous.output.len());
+            (actual.output[..len] != previous.output[..len]).then_some("output")
+        } else if actual.output != previous.output {
+            Some("output")
+        } else if actual.error != previous.error {
+            Some("result")
+        } else if actual.memory != previous.memory {
+            Some("tape")
+        } else if actual.pointer != previous.pointer {
+            Some("pointer")
+        } else {
+            None
+        };
+        if let Some(what) = what {
+            return Err(VerifyError::Diverged { pass, what });
+        }
+        previous = actual;
+    }
+    Ok(previous.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<ASTNode> {
+        Parser::new(Lexer::new(String::from(source)))
+            .parse_helper()
+            .data
+    }
+
+    /// Random balanced program of about `len` commands, from a xorshift state
+    fn random_program(state: &mut u64, len: usize, depth: usize) -> String {
+        let mut program = String::new();
+        while program.len() < len {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            match *state % 16 {
+                0..=3 => program.push('+'),
+                4..=5 => program.push('-'),
+                6..=8 => program.push('>'),
+                9..=10 => program.push('<'),
+                11 => program.push('.'),
+                12 => program.push(','),
+                _ if depth < 3 => {
+                    let body = random_program(state, len / 3, depth + 1);
+                    program.push_str(&format!("[{}]", body));
+                }
+                _ => program.push('-'),
+            }
+        }
+        program
+    }
+
+    #[test]
+    fn test_verify() {
+        let output = verify(&parse("++++++++[>++++++++<-]>+.+.,."), b"!", None, 64).unwrap();
+        assert_eq!(output, b"AB!");
+
+        // partial evaluation used to print the `.` before the move past the
+        // end of a small tape that stops the program first
+        let source = format!("{}+{}.", ">".repeat(20), "<".repeat(20));
+        assert_eq!(verify(&parse(&source), b"", None, 16).unwrap(), b"");
+
+        // partial evaluation used to run steps at compile time that the
+        // limit never saw, and print `3` as well
+        assert_eq!(
+            verify(&parse("+.+.+.+.+.+."), b"", Some(4), 64).unwrap(),
+            [1, 2]
+        );
+        assert_eq!(verify(&parse("+[>+<]"), b"", Some(1000), 64).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_random_programs() {
+        let mut state = 0x2545f4914f6cdd1d;
+        let mut verified = 0;
+        for _ in 0..2000 {
+            let source = random_program(&mut state, 24, 0);
+            match verify(&parse(&source), b"\x03\x01\xff", Some(20_000), 16) {
+                Ok(_) => verified += 1,
+                Err(VerifyError::Unfinished(_)) => {}
+                Err(err) => panic!("{}: {}", source, err),
+            }
+        }
+        assert!(verified > 500, "only {} programs finished", verified);
+    }
+}
@@ -1,3 +1,3 @@
 mod parser;
 
-pub use self::parser::{ASTNode, Parser};
+pub use self::parser::{ASTNode, AbstractSyntaxTree, ParseError, Parser};
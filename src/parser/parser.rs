@@ -1,16 +1,18 @@
+use std::fmt;
 use std::time::Duration;
 
 use crate::{
-    lexer::{Lexer, Token},
+    lexer::{Lexer, Span, Token},
     util::unlikely,
 };
 
 pub struct Parser {
     lexer: Lexer,
     loop_count: u8,
+    spans: Vec<Span>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ASTNode {
     Incr(u8),
     Decr(u8),
@@ -19,9 +21,6 @@ pub enum ASTNode {
     Loop(Vec<ASTNode>),
     Input,
     Output,
-    Set(u8),
-    ScanLeft,
-    ScanRight,
     Comment(char),
     NoOp,
 }
@@ -29,13 +28,34 @@ pub enum ASTNode {
 #[derive(Debug, PartialEq)]
 pub struct AbstractSyntaxTree {
     pub data: Vec<ASTNode>,
+    /// Source span of every node in pre-order. A loop contributes the span of
+    /// its `[` before the spans of its body and the span of its `]` after them.
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    UnmatchedLoopStart,
+    UnmatchedLoopEnd,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnmatchedLoopStart => write!(f, "Unmatched loop start"),
+            ParseError::UnmatchedLoopEnd => write!(f, "Unmatched loop end"),
+        }
+    }
 }
 
+impl std::error::Error for ParseError {}
+
 impl Parser {
     pub fn new(lexer: Lexer) -> Self {
         Parser {
             lexer,
             loop_count: 0,
+            spans: Vec::new(),
         }
     }
 
@@ -48,10 +68,30 @@ impl Parser {
     }
 
     pub fn parse_helper(&mut self) -> AbstractSyntaxTree {
+        match self.try_parse() {
+            Ok(tree) => tree,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Like `parse_helper`, but reports unbalanced loops instead of panicking
+    pub fn try_parse(&mut self) -> Result<AbstractSyntaxTree, ParseError> {
+        let data = self.parse_nodes()?;
+        let spans = std::mem::take(&mut self.spans);
+
+        Ok(AbstractSyntaxTree { data, spans })
+    }
+
+    fn parse_nodes(&mut self) -> Result<Vec<ASTNode>, ParseError> {
         let mut tree = Vec::new();
 
-        while let Some(token) = self.lexer.next_token() {
-            match token {
+        loop {
+            let span = self.lexer.start_span();
+            let Some(token) = self.lexer.next_token() else {
+                break;
+            };
+
+            let node = match token {
                 Token::Incr => {
                     let mut count = 1;
                     while let Some(Token::Incr) = self.lexer.peek_token() {
@@ -61,7 +101,7 @@ impl Parser {
                         self.lexer.next_token();
                         count += 1;
                     }
-                    tree.push(ASTNode::Incr(count));
+                    ASTNode::Incr(count)
                 }
                 Token::Decr => {
                     let mut count = 1;
@@ -72,7 +112,7 @@ impl Parser {
                         self.lexer.next_token();
                         count += 1;
                     }
-                    tree.push(ASTNode::Decr(count));
+                    ASTNode::Decr(count)
                 }
                 Token::Next => {
                     let mut count = 1;
@@ -80,7 +120,7 @@ impl Parser {
                         self.lexer.next_token();
                         count += 1;
                     }
-                    tree.push(ASTNode::Next(count));
+                    ASTNode::Next(count)
                 }
                 Token::Prev => {
                     let mut count = 1;
@@ -88,31 +128,37 @@ impl Parser {
                         self.lexer.next_token();
                         count += 1;
                     }
-                    tree.push(ASTNode::Prev(count));
+                    ASTNode::Prev(count)
                 }
-                Token::Output => tree.push(ASTNode::Output),
-                Token::Input => tree.push(ASTNode::Input),
+                Token::Output => ASTNode::Output,
+                Token::Input => ASTNode::Input,
                 Token::LoopStart => {
+                    self.spans.push(self.lexer.extend_span(span));
                     self.loop_count += 1;
-                    tree.push(ASTNode::Loop(self.parse_helper().data));
+                    tree.push(ASTNode::Loop(self.parse_nodes()?));
+                    continue;
                 }
                 Token::LoopEnd => {
                     if self.loop_count == 0 {
-                        panic!("Unmatched loop end");
+                        return Err(ParseError::UnmatchedLoopEnd);
                     }
 
+                    self.spans.push(self.lexer.extend_span(span));
                     self.loop_count -= 1;
                     break;
                 }
-                Token::Other(c) => tree.push(ASTNode::Comment(c)),
-            }
+                Token::Other(c) => ASTNode::Comment(c),
+            };
+
+            self.spans.push(self.lexer.extend_span(span));
+            tree.push(node);
         }
 
         if unlikely(self.lexer.peek_token().is_none() && self.loop_count != 0) {
-            panic!("Unmatched loop start");
+            return Err(ParseError::UnmatchedLoopStart);
         }
 
-        AbstractSyntaxTree { data: tree }
+        Ok(tree)
     }
 }
 
@@ -158,10 +204,43 @@ mod tests {
         parser.parse();
     }
 
+    #[test]
+    fn test_spans() {
+        let mut parser = Parser::new(Lexer::new(String::from("++[\n>]")));
+        let ast = parser.parse_helper();
+
+        let spans: Vec<_> = ast
+            .spans
+            .iter()
+            .map(|span| (span.offset, span.len, span.line, span.column))
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                (0, 2, 1, 1),
+                (2, 1, 1, 3),
+                (3, 1, 1, 4),
+                (4, 1, 2, 1),
+                (5, 1, 2, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_try_parse_errors() {
+        let mut parser = Parser::new(Lexer::new(String::from("[[]")));
+        assert_eq!(parser.try_parse(), Err(ParseError::UnmatchedLoopStart));
+
+        let mut parser = Parser::new(Lexer::new(String::from("[]]")));
+        assert_eq!(parser.try_parse(), Err(ParseError::UnmatchedLoopEnd));
+    }
+
     #[test]
     fn test_noop() {
         let ast = AbstractSyntaxTree {
             data: vec![ASTNode::NoOp],
+            spans: vec![],
         };
         let mut iter = ast.data.iter();
 
@@ -0,0 +1,5 @@
+mod profiler;
+mod trace;
+
+pub use self::profiler::{loop_source, LoopProfile, Profile};
+pub use self::trace::{write_chrome_trace, write_collapsed, TraceFormat};
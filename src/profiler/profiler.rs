@@ -0,0 +1,330 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use crate::interpreter::Instruction;
+use crate::lexer::Span;
+
+/// Longest loop source shown in a report, longer loops are cut off
+const MAX_SOURCE_LEN: usize = 60;
+
+/// Execution counts of one loop, from its `BeginLoop` to its `EndLoop`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    pub start: usize,
+    pub end: usize,
+    pub parent: Option<usize>,
+    /// Times the loop was reached, whether or not the body ran
+    pub entries: u64,
+    pub iterations: u64,
+    /// Instructions executed in the loop, including nested loops
+    pub steps: u64,
+    /// Instructions executed in the loop but outside nested loops
+    pub self_steps: u64,
+}
+
+/// Summary of the counts collected by `Interpreter::set_profiling`
+pub struct Profile<'a> {
+    program: &'a [Instruction],
+    counts: &'a [u64],
+    loops: Vec<LoopProfile>,
+    total: u64,
+    /// Source and the span of every instruction, see `with_source`
+    source: Option<(&'a str, &'a [Span])>,
+}
+
+impl<'a> Profile<'a> {
+    pub fn new(program: &'a [Instruction], counts: &'a [u64]) -> Self {
+        let mut loops: Vec<LoopProfile> = Vec::new();
+        let mut open = Vec::new();
+
+        for (index, instr) in program.iter().enumerate() {
+            match instr {
+                Instruction::BeginLoop(_) => {
+                    open.push(loops.len());
+                    loops.push(LoopProfile {
+                        start: index,
+                        end: index,
+                        parent: None,
+                        entries: counts[index],
+                        iterations: 0,
+                        steps: 0,
+                        self_steps: 0,
+                    });
+                }
+                instr if instr.end_loop().is_some() => {
+                    let current = open.pop().expect("unbalanced loop");
+                    let start = loops[current].start;
+                    let steps = counts[start..=index].iter().sum();
+
+                    let profile = &mut loops[current];
+                    profile.end = index;
+                    profile.parent = open.last().copied();
+                    profile.iterations = counts[index];
+                    profile.steps = steps;
+                    profile.self_steps = steps;
+                }
+                _ => {}
+            }
+        }
+
+        for index in 0..loops.len() {
+            if let Some(parent) = loops[index].parent {
+                loops[parent].self_steps -= loops[index].steps;
+            }
+        }
+
+        Profile {
+            program,
+            counts,
+            loops,
+            total: counts.iter().sum(),
+            source: None,
+        }
+    }
+
+    /// Shows loops as their text in `source`, for the profile of an
+    /// unoptimized program whose instructions have the source `spans` made by
+    /// `debugger::source_map`, so every loop is a `Loop` of the source
+    pub fn with_source(mut self, source: &'a str, spans: &'a [Span]) -> Self {
+        self.source = Some((source, spans));
+        self
+    }
+
+    /// Loops in program order
+    pub fn loops(&self) -> &[LoopProfile] {
+        &self.loops
+    }
+
+    pub fn total_steps(&self) -> u64 {
+        self.total
+    }
+
+    /// The `n` loops with the most instructions executed outside their nested
+    /// loops, which are where new optimizations pay off
+    pub fn hot_loops(&self, n: usize) -> Vec<&LoopProfile> {
+        let mut loops: Vec<_> = self.loops.iter().filter(|l| l.steps > 0).collect();
+        loops.sort_by(|a, b| b.self_steps.cmp(&a.self_steps).then(a.start.cmp(&b.start)));
+        loops.truncate(n);
+        loops
+    }
+
+    /// Executions per kind of instruction, most frequent first
+    pub fn instruction_counts(&self) -> Vec<(&'static str, u64)> {
+        let mut kinds: Vec<(&'static str, u64)> = Vec::new();
+        for (instr, &count) in self.program.iter().zip(self.counts) {
+            let name = kind(instr);
+            match kinds.iter_mut().find(|(kind, _)| *kind == name) {
+                Some((_, total)) => *total += count,
+                None => kinds.push((name, count)),
+            }
+        }
+        kinds.sort_by_key(|(_, count)| Reverse(*count));
+        kinds
+    }
+
+    /// Text of the loop on one line, taken from the source when it's known
+    /// and otherwise printed from its instructions, see `loop_source`
+    pub fn source(&self, profile: &LoopProfile) -> String {
+        let Some((source, spans)) = self.source else {
+            return loop_source(&self.program[profile.start..=profile.end]);
+        };
+        let (start, end) = (&spans[profile.start], &spans[profile.end]);
+        let text: String = source
+            .chars()
+            .skip(start.offset)
+            .take(end.offset + end.len - start.offset)
+            .collect();
+        shorten(text.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+
+    /// Where the loop starts, `line:column` in the source when it's known and
+    /// otherwise `@` and the index of its first instruction
+    pub fn location(&self, profile: &LoopProfile) -> String {
+        match self.source {
+            Some((_, spans)) => {
+                let span = &spans[profile.start];
+                format!("{}:{}", span.line, span.column)
+            }
+            None => format!("@{}", profile.start),
+        }
+    }
+
+    pub fn report(&self, top: usize, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{} instructions executed", self.total)?;
+        writeln!(out)?;
+        writeln!(out, "{:<12} {:>14} {:>7}", "instruction", "count", "share")?;
+        for (name, count) in self.instruction_counts() {
+            writeln!(
+                out,
+                "{:<12} {:>14} {:>6.2}%",
+                name,
+                count,
+                self.share(count)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:>4} {:>13} {:>7} {:>7}  source",
+            "#", "iterations", "self", "total"
+        )?;
+        for (rank, profile) in self.hot_loops(top).into_iter().enumerate() {
+            writeln!(
+                out,
+                "{:>4} {:>13} {:>6.2}% {:>6.2}%  {} {}",
+                rank + 1,
+                profile.iterations,
+                self.share(profile.self_steps),
+                self.share(profile.steps),
+                self.source(profile),
+                self.location(profile),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => count as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+/// Brainfuck text of a loop, cut off when it's too long to print
+pub fn loop_source(program: &[Instruction]) -> String {
+    shorten(program.iter().map(ToString::to_string).collect())
+}
+
+/// Cuts `text` off after `MAX_SOURCE_LEN` characters
+fn shorten(text: String) -> String {
+    match text.char_indices().nth(MAX_SOURCE_LEN) {
+        Some((cut, _)) => format!("{}...", &text[..cut]),
+        None => text,
+    }
+}
+
+fn kind(instr: &Instruction) -> &'static str {
+    match instr {
+        Instruction::Incr(_) => "Incr",
+        Instruction::Decr(_) => "Decr",
+        Instruction::Next(_) => "Next",
+        Instruction::Prev(_) => "Prev",
+        Instruction::Input => "Input",
+        Instruction::Output => "Output",
+        Instruction::BeginLoop(_) => "BeginLoop",
+        Instruction::EndLoop(_) => "EndLoop",
+        Instruction::ScanRight => "ScanRight",
+        Instruction::ScanLeft => "ScanLeft",
+        Instruction::Set(_) => "Set",
+        Instruction::SetNext(..) => "SetNext",
+        Instruction::SetPrev(..) => "SetPrev",
+        Instruction::NextEndLoop(..) => "NextEndLoop",
+        Instruction::PrevEndLoop(..) => "PrevEndLoop",
+        Instruction::DecrEndLoop(..) => "DecrEndLoop",
+        Instruction::NextScanRight(_) => "NextScanRight",
+        Instruction::PrevScanLeft(_) => "PrevScanLeft",
+        Instruction::OutputBytes(_) => "OutputBytes",
+        Instruction::If(_) => "If",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn profile(program: &str) -> (Vec<Instruction>, Vec<u64>) {
+        let mut parser = Parser::new(Lexer::new(String::from(program)));
+        let ast = parser.parse_helper();
+        let mut interpreter = Interpreter::new(ast.data);
+        interpreter.set_profiling(true);
+        interpreter.run().unwrap();
+        (
+            interpreter.program().to_vec(),
+            interpreter.profile().unwrap().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_loops() {
+        let (program, counts) = profile("++[->+++[-]<]");
+        let profile = Profile::new(&program, &counts);
+
+        assert_eq!(profile.total_steps(), 26);
+        assert_eq!(
+            profile.loops(),
+            [
+                LoopProfile {
+                    start: 1,
+                    end: 9,
+                    parent: None,
+                    entries: 1,
+                    iterations: 2,
+                    steps: 25,
+                    self_steps: 11,
+                },
+                LoopProfile {
+                    start: 5,
+                    end: 7,
+                    parent: Some(0),
+                    entries: 2,
+                    iterations: 6,
+                    steps: 14,
+                    self_steps: 14,
+                },
+            ]
+        );
+
+        let hot: Vec<_> = profile.hot_loops(1).iter().map(|l| l.start).collect();
+        assert_eq!(hot, [5]);
+        assert_eq!(profile.source(&profile.loops()[0]), "[->+++[-]<]");
+    }
+
+    #[test]
+    fn test_instruction_counts() {
+        let (program, counts) = profile("+++[-]");
+        let profile = Profile::new(&program, &counts);
+
+        assert_eq!(
+            profile.instruction_counts(),
+            [("Decr", 3), ("EndLoop", 3), ("Incr", 1), ("BeginLoop", 1)]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let (program, counts) = profile(&format!("+[-{}]", ">".repeat(30) + &"<".repeat(30)));
+        let profile = Profile::new(&program, &counts);
+
+        let mut report = Vec::new();
+        profile.report(10, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.starts_with("6 instructions executed"));
+        assert!(report.contains(&format!("[-{}...", ">".repeat(30) + &"<".repeat(28))));
+    }
+
+    #[test]
+    fn test_source() {
+        let source = "++\n[->+++ clear [-]\n\t<]";
+        let ast = Parser::new(Lexer::new(String::from(source))).parse_helper();
+        let spans = crate::debugger::source_map(&ast.data, &ast.spans);
+        let (program, counts) = profile(source);
+        let profile = Profile::new(&program, &counts).with_source(source, &spans);
+
+        let loops = profile.loops();
+        assert_eq!(profile.source(&loops[0]), "[->+++ clear [-] <]");
+        assert_eq!(profile.location(&loops[0]), "2:1");
+        assert_eq!(profile.source(&loops[1]), "[-]");
+        assert_eq!(profile.location(&loops[1]), "2:14");
+
+        let mut report = Vec::new();
+        profile.report(10, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("[->+++ clear [-] <] 2:1"), "{}", report);
+    }
+}
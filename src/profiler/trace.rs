@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+
+use super::profiler::{loop_source, Profile};
+use crate::emit::json_string;
+use crate::interpreter::{Instruction, TraceEvent};
+
+/// Output formats of `--trace`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// Chrome `trace_event` JSON, for chrome://tracing, Perfetto or speedscope
+    Chrome,
+    /// Collapsed stacks, for flamegraph.pl, inferno or speedscope
+    Collapsed,
+}
+
+/// Source of the loop starting at `program_counter`. Loops can contain
+/// `OutputBytes`, so it can contain any printable character.
+fn source(program: &[Instruction], program_counter: usize) -> String {
+    let end = match program[program_counter] {
+        Instruction::BeginLoop(offset) => program_counter + offset,
+        _ => program_counter,
+    };
+    loop_source(&program[program_counter..=end])
+}
+
+/// Name of the frame of the loop starting at `program_counter`
+fn frame(program: &[Instruction], program_counter: usize) -> String {
+    format!("{} @{}", source(program, program_counter), program_counter)
+}
+
+/// `frame` for collapsed stacks, which separate frames with `;`, so the loop
+/// source can't contain one. Spaces are fine, flamegraph.pl, inferno and
+/// speedscope take the count after the last space of the line.
+fn collapsed_frame(program: &[Instruction], program_counter: usize) -> String {
+    frame(program, program_counter).replace(';', "\\x3b")
+}
+
+/// Writes loop runs as complete events on a single thread, one microsecond
+/// per executed instruction
+pub fn write_chrome_trace(
+    program: &[Instruction],
+    events: &[TraceEvent],
+    total_steps: u64,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut events = events.to_vec();
+    events.sort_by_key(|event| (event.start, event.depth));
+
+    writeln!(out, "{{\"traceEvents\":[")?;
+    write!(
+        out,
+        "{{\"name\":\"program\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":0,\"dur\":{}}}",
+        total_steps
+    )?;
+    for event in events {
+        write!(
+            out,
+            ",\n{{\"name\":{},\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":{},\"dur\":{}}}",
+            json_string(&frame(program, event.program_counter)),
+            event.start,
+            event.steps
+        )?;
+    }
+    writeln!(out, "\n]}}")
+}
+
+/// Writes the instructions executed directly in every loop, under the stack
+/// of loops it's nested in
+pub fn write_collapsed(
+    profile: &Profile,
+    program: &[Instruction],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let loops = profile.loops();
+    let nested: u64 = loops
+        .iter()
+        .filter(|l| l.parent.is_none())
+        .map(|l| l.steps)
+        .sum();
+    if profile.total_steps() > nested {
+        writeln!(out, "program {}", profile.total_steps() - nested)?;
+    }
+
+    for (index, profile) in loops.iter().enumerate() {
+        if profile.self_steps == 0 {
+            continue;
+        }
+
+        let mut stack = vec![collapsed_frame(program, profile.start)];
+        let mut parent = loops[index].parent;
+        while let Some(index) = parent {
+            stack.push(collapsed_frame(program, loops[index].start));
+            parent = loops[index].parent;
+        }
+        stack.push(String::from("program"));
+        stack.reverse();
+
+        writeln!(out, "{} {}", stack.join(";"), profile.self_steps)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::ir::lower;
+    use crate::lexer::Lexer;
+    use crate::optimizer::coalesce_output;
+    use crate::parser::Parser;
+
+    fn interpreter(program: &str) -> Interpreter<'static> {
+        let mut parser = Parser::new(Lexer::new(String::from(program)));
+        let ast = parser.parse_helper();
+        Interpreter::new(ast.data)
+    }
+
+    #[test]
+    fn test_collapsed() {
+        let mut interpreter = interpreter("++[->+++[-]<]+");
+        interpreter.set_profiling(true);
+        interpreter.run().unwrap();
+
+        let profile = Profile::new(interpreter.program(), interpreter.profile().unwrap());
+        let mut out = Vec::new();
+        write_collapsed(&profile, interpreter.program(), &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "program 2\n\
+             program;[->+++[-]<] @1 11\n\
+             program;[->+++[-]<] @1;[-] @5 14\n"
+        );
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let mut interpreter = interpreter("++[->+++[-]<]");
+        interpreter.set_tracing(Some(0));
+        interpreter.run().unwrap();
+
+        let events = interpreter.take_trace();
+        let mut out = Vec::new();
+        write_chrome_trace(
+            interpreter.program(),
+            &events,
+            interpreter.steps(),
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"traceEvents\":[\n\
+             {\"name\":\"program\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":0,\"dur\":26},\n\
+             {\"name\":\"[->+++[-]<] @1\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":1,\"dur\":25},\n\
+             {\"name\":\"[-] @5\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":5,\"dur\":7},\n\
+             {\"name\":\"[-] @5\",\"ph\":\"X\",\"pid\":1,\"tid\":1,\"ts\":17,\"dur\":7}\n\
+             ]}\n"
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        // the loop prints " \";" as one `OutputBytes`
+        let source = format!(",[[-]{}.++.{}.[-],]", "+".repeat(32), "+".repeat(25));
+        let ast = Parser::new(Lexer::new(source)).parse_helper();
+        let program = Interpreter::compile_ir(&coalesce_output(lower(&ast.data)));
+
+        let run = |tracing: bool| {
+            let mut interpreter = Interpreter::builder()
+                .input(&b"a"[..])
+                .output(std::io::sink())
+                .build_with_instructions(program.clone());
+            if tracing {
+                interpreter.set_tracing(Some(0));
+            } else {
+                interpreter.set_profiling(true);
+            }
+            interpreter.run().unwrap();
+            interpreter
+        };
+
+        let mut interpreter = run(true);
+        let events = interpreter.take_trace();
+        let mut out = Vec::new();
+        write_chrome_trace(
+            interpreter.program(),
+            &events,
+            interpreter.steps(),
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"\" \\\";\""#), "{}", out);
+
+        let interpreter = run(false);
+        let profile = Profile::new(interpreter.program(), interpreter.profile().unwrap());
+        let mut out = Vec::new();
+        write_collapsed(&profile, interpreter.program(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // the outer loop's own line, with the program as the only other frame
+        // and the count after the last space
+        let line = out.lines().nth(1).unwrap();
+        let (stack, count) = line.rsplit_once(' ').unwrap();
+        assert!(count.parse::<u64>().is_ok(), "{}", out);
+        assert!(stack.contains(r#"" \"\x3b""#), "{}", out);
+        assert_eq!(stack.split(';').count(), 2);
+    }
+}
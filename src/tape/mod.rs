@@ -0,0 +1,3 @@
+mod tape;
+
+pub use self::tape::{dump, report_heatmap};
@@ -0,0 +1,158 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::interpreter::TapeHeatmap;
+
+const CELLS_PER_LINE: usize = 16;
+const HEAT_CELLS_PER_LINE: usize = 64;
+/// Most accessed cells listed after the heat map
+const HOTTEST_CELLS: usize = 10;
+/// From cells that were never accessed to the most accessed ones
+const HEAT: &[u8] = b" .:-=+*#%@";
+
+/// Prints cells as hex and ASCII, collapsing repeated lines of zeros into `*`
+pub fn dump(memory: &[u8], range: Range<usize>, out: &mut dyn Write) -> io::Result<()> {
+    let range = range.start.min(memory.len())..range.end.min(memory.len());
+    let mut skipping = false;
+
+    for start in range.clone().step_by(CELLS_PER_LINE) {
+        let cells = &memory[start..(start + CELLS_PER_LINE).min(range.end)];
+
+        let zeros = cells.iter().all(|&cell| cell == 0);
+        let last = start + CELLS_PER_LINE >= range.end;
+        if zeros && start != range.start && !last {
+            if !skipping {
+                writeln!(out, "*")?;
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+
+        let hex: Vec<String> = cells.iter().map(|cell| format!("{:02x}", cell)).collect();
+        let ascii: String = cells
+            .iter()
+            .map(|&cell| match cell {
+                b' '..=b'~' => cell as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(
+            out,
+            "{:>6}: {:<width$}  |{}|",
+            start,
+            hex.join(" "),
+            ascii,
+            width = CELLS_PER_LINE * 3 - 1
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the tape footprint, a heat map of the used cells and the most
+/// accessed cells
+pub fn report_heatmap(heatmap: &TapeHeatmap, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "max pointer position: {}", heatmap.max_pointer)?;
+    let Some((first, last)) = heatmap.used() else {
+        return writeln!(out, "no cells were accessed");
+    };
+    writeln!(
+        out,
+        "cells accessed: {}..={} ({} cells)",
+        first,
+        last,
+        last - first + 1
+    )?;
+
+    let accesses: Vec<u64> = heatmap
+        .reads
+        .iter()
+        .zip(&heatmap.writes)
+        .map(|(reads, writes)| reads + writes)
+        .collect();
+    let hottest = accesses.iter().copied().max().unwrap_or(0);
+    let heat = |count: u64| -> char {
+        if count == 0 {
+            return ' ';
+        }
+        let level = ((count as f64).ln_1p() / (hottest as f64).ln_1p() * (HEAT.len() - 1) as f64)
+            .ceil() as usize;
+        HEAT[level.clamp(1, HEAT.len() - 1)] as char
+    };
+
+    writeln!(out)?;
+    let start = first - first % HEAT_CELLS_PER_LINE;
+    for row in (start..=last).step_by(HEAT_CELLS_PER_LINE) {
+        let end = (row + HEAT_CELLS_PER_LINE).min(last + 1);
+        let line: String = accesses[row..end]
+            .iter()
+            .map(|&count| heat(count))
+            .collect();
+        writeln!(out, "{:>6} |{}", row, line.trim_end())?;
+    }
+
+    let mut cells: Vec<usize> = (first..=last).filter(|&cell| accesses[cell] > 0).collect();
+    cells.sort_by_key(|&cell| std::cmp::Reverse(accesses[cell]));
+
+    writeln!(out)?;
+    writeln!(out, "{:>6} {:>14} {:>14}", "cell", "reads", "writes")?;
+    for cell in cells.into_iter().take(HOTTEST_CELLS) {
+        writeln!(
+            out,
+            "{:>6} {:>14} {:>14}",
+            cell, heatmap.reads[cell], heatmap.writes[cell]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let mut memory = vec![0; 100];
+        memory[..5].copy_from_slice(b"Hi!\n\x7f");
+        memory[70] = 0xff;
+
+        let mut out = Vec::new();
+        dump(&memory, 0..80, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "     0: 48 69 21 0a 7f 00 00 00 00 00 00 00 00 00 00 00  |Hi!.............|\n\
+             *\n    \
+             64: 00 00 00 00 00 00 ff 00 00 00 00 00 00 00 00 00  |................|\n"
+        );
+
+        // ranges are clamped to the tape
+        let mut out = Vec::new();
+        dump(&memory, 98..200, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "    98: 00 00                                            |..|\n"
+        );
+    }
+
+    #[test]
+    fn test_report_heatmap() {
+        let heatmap = TapeHeatmap {
+            reads: vec![0, 100, 1, 0, 5],
+            writes: vec![0, 100, 0, 0, 0],
+            max_pointer: 4,
+        };
+
+        let mut out = Vec::new();
+        report_heatmap(&heatmap, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "max pointer position: 4\n\
+             cells accessed: 1..=4 (4 cells)\n\
+             \n     0 | @: =\n\
+             \n  cell          reads         writes\n     \
+             1            100            100\n     \
+             4              5              0\n     \
+             2              1              0\n"
+        );
+    }
+}
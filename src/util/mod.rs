@@ -1,3 +1,3 @@
 mod util;
 
-pub use self::util::{likely, print_debug, unlikely};
+pub use self::util::{likely, print_debug, print_error, print_note, print_warning, unlikely};
@@ -46,3 +46,15 @@ pub fn print_debug(
     );
     println!();
 }
+
+pub fn print_error(err: &dyn std::fmt::Display) {
+    eprintln!("{}", format!("[ ERROR ] {}", err).red().bold());
+}
+
+pub fn print_warning(warning: &dyn std::fmt::Display) {
+    eprintln!("{}", format!("[ WARN  ] {}", warning).yellow().bold());
+}
+
+pub fn print_note(note: &dyn std::fmt::Display) {
+    eprintln!("{}", format!("[ NOTE  ] {}", note).cyan().bold());
+}
@@ -0,0 +1,3 @@
+mod viz;
+
+pub use self::viz::Visualizer;
@@ -0,0 +1,343 @@
+use std::io::{self, BufRead, Write};
+use std::thread;
+use std::time::Duration;
+
+use colored::Colorize;
+
+use crate::interpreter::{Event, Interpreter};
+use crate::lexer::Span;
+
+/// Source lines shown around the current instruction
+const SOURCE_HEIGHT: usize = 9;
+/// Width of listings decompiled from optimized programs
+const LISTING_WIDTH: usize = 64;
+/// Cells shown on either side of the memory pointer
+const TAPE_RADIUS: usize = 5;
+/// Most recent lines of program output shown
+const OUTPUT_HEIGHT: usize = 6;
+/// Frames are skipped rather than drawn faster than this
+const MAX_FPS: u64 = 30;
+
+/// Animates a program in the terminal: its source with the current instruction
+/// highlighted, the tape around the memory pointer and the program output.
+/// Driven by `Interpreter::step`, like the debugger.
+pub struct Visualizer<'a> {
+    interpreter: Interpreter<'a>,
+    lines: Vec<String>,
+    spans: Vec<Span>,
+    output: Vec<u8>,
+    status: Option<String>,
+}
+
+impl<'a> Visualizer<'a> {
+    /// `spans` maps the instructions back to `source`, see
+    /// `debugger::source_map`. Without it, e.g. for optimized programs, the
+    /// instructions themselves are shown.
+    pub fn new(source: &str, spans: Option<Vec<Span>>, interpreter: Interpreter<'a>) -> Self {
+        let (lines, spans) = match spans {
+            Some(spans) => (source.lines().map(String::from).collect(), spans),
+            None => listing(&interpreter),
+        };
+
+        Visualizer {
+            interpreter,
+            lines,
+            spans,
+            output: Vec::new(),
+            status: None,
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Executes one instruction, reading program input from `input`. Returns
+    /// false once the program stopped.
+    pub fn step(&mut self, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<bool> {
+        if self.status.is_some() {
+            return Ok(false);
+        }
+
+        loop {
+            match self.interpreter.step() {
+                Ok(Event::Paused) => break,
+                Ok(Event::Output(byte)) => {
+                    self.output.push(byte);
+                    break;
+                }
+                Ok(Event::NeedsInput) => {
+                    write!(out, "{} ", "input>".bold())?;
+                    out.flush()?;
+
+                    let mut line = String::new();
+                    if input.read_line(&mut line)? == 0 {
+                        self.interpreter.close_input();
+                    }
+                    self.interpreter.push_input(line.as_bytes());
+                }
+                Ok(Event::Halted) => {
+                    self.status = Some(String::from("halted"));
+                    return Ok(false);
+                }
+                Err(err) => {
+                    self.status = Some(format!("stopped: {}", err));
+                    return Ok(false);
+                }
+            }
+        }
+
+        if self.interpreter.program_counter() >= self.interpreter.program().len() {
+            self.status = Some(String::from("halted"));
+        }
+        Ok(self.status.is_none())
+    }
+
+    /// Animates the program at `speed` instructions per second until it stops
+    pub fn run(
+        &mut self,
+        speed: u64,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let speed = speed.max(1);
+        let steps_per_frame = speed.div_ceil(MAX_FPS);
+        let delay = Duration::from_secs_f64(steps_per_frame as f64 / speed as f64);
+
+        loop {
+            self.draw(out)?;
+
+            for _ in 0..steps_per_frame {
+                if !self.step(input, out)? {
+                    return self.draw(out);
+                }
+            }
+            thread::sleep(delay);
+        }
+    }
+
+    /// Redraws the whole screen in place
+    fn draw(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut frame = Vec::new();
+        self.render(&mut frame)?;
+
+        // move home, clear the rest of every line and everything below
+        write!(out, "\x1b[H")?;
+        for line in String::from_utf8_lossy(&frame).lines() {
+            writeln!(out, "{}\x1b[K", line)?;
+        }
+        write!(out, "\x1b[J")?;
+        out.flush()
+    }
+
+    /// Writes a single frame
+    pub fn render(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.render_source(out)?;
+        writeln!(out)?;
+        self.render_tape(out)?;
+        writeln!(out)?;
+        self.render_output(out)?;
+        writeln!(out)?;
+
+        let pc = self.interpreter.program_counter();
+        let instruction = match self.interpreter.program().get(pc) {
+            Some(instruction) => format!("@{} {:?}", pc, instruction),
+            None => format!("@{} <end>", pc),
+        };
+        write!(out, "step {}  {}", self.interpreter.steps(), instruction)?;
+        if let Some(status) = &self.status {
+            write!(out, "  {}", status.bold())?;
+        }
+        writeln!(out)
+    }
+
+    fn render_source(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", "source".bold())?;
+
+        let current = self.spans.get(self.interpreter.program_counter());
+        let line = current.map_or(self.lines.len(), |span| span.line);
+        let first = line.saturating_sub(SOURCE_HEIGHT / 2 + 1);
+
+        for (index, text) in self
+            .lines
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(SOURCE_HEIGHT)
+        {
+            write!(out, "{:>5} | ", index + 1)?;
+            match current {
+                Some(span) if span.line == index + 1 => {
+                    let before: String = text.chars().take(span.column - 1).collect();
+                    let at: String = text.chars().skip(span.column - 1).take(span.len).collect();
+                    let after: String = text.chars().skip(span.column - 1 + span.len).collect();
+                    writeln!(out, "{}{}{}", before, at.black().on_yellow(), after)?;
+                    writeln!(
+                        out,
+                        "      | {}{}",
+                        " ".repeat(span.column - 1),
+                        "^".repeat(span.len)
+                    )?;
+                }
+                _ => writeln!(out, "{}", text)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn render_tape(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", "tape".bold())?;
+
+        let memory = self.interpreter.memory();
+        let pointer = self.interpreter.memory_pointer();
+        let start = pointer.saturating_sub(TAPE_RADIUS);
+        let end = (start + 2 * TAPE_RADIUS + 1).min(memory.len());
+
+        let mut cells = String::from(" cell ");
+        let mut values = String::from("value ");
+        let mut marker = String::from("      ");
+        for (index, &value) in memory.iter().enumerate().take(end).skip(start) {
+            let cell = format!("{:>6}", index);
+            let value = format!("{:>6}", value);
+            if index == pointer {
+                cells.push_str(&cell.bold().to_string());
+                values.push_str(&value.black().on_yellow().to_string());
+                marker.push_str("     ^");
+            } else {
+                cells.push_str(&cell);
+                values.push_str(&value);
+                marker.push_str("      ");
+            }
+        }
+
+        writeln!(out, "{}", cells)?;
+        writeln!(out, "{}", values)?;
+        writeln!(out, "{}", marker.trim_end())
+    }
+
+    fn render_output(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", "output".bold())?;
+
+        let output: String = String::from_utf8_lossy(&self.output)
+            .chars()
+            .filter(|c| *c == '\n' || !c.is_control())
+            .collect();
+        let lines: Vec<&str> = output.split('\n').collect();
+        for line in &lines[lines.len().saturating_sub(OUTPUT_HEIGHT)..] {
+            writeln!(out, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lines of brainfuck decompiled from the instructions, with the position of
+/// every instruction in them
+fn listing(interpreter: &Interpreter) -> (Vec<String>, Vec<Span>) {
+    let mut lines = vec![String::new()];
+    let mut spans = Vec::new();
+    let mut offset = 0;
+
+    for instruction in interpreter.program() {
+        let text = instruction.to_string();
+        let len = text.chars().count();
+
+        let width = lines.last().map_or(0, |line| line.chars().count());
+        if width > 0 && width + len > LISTING_WIDTH {
+            lines.push(String::new());
+        }
+
+        let column = lines.last().map_or(0, |line| line.chars().count()) + 1;
+        spans.push(Span {
+            offset,
+            len,
+            line: lines.len(),
+            column,
+        });
+        let line = lines.last_mut().unwrap();
+        line.push_str(&text);
+        offset += len;
+    }
+
+    (lines, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::source_map;
+    use crate::lexer::Lexer;
+    use crate::optimizer::{Optimizer, OptimizerOptions};
+    use crate::parser::Parser;
+
+    fn render(visualizer: &Visualizer) -> String {
+        colored::control::set_override(false);
+        let mut frame = Vec::new();
+        visualizer.render(&mut frame).unwrap();
+        String::from_utf8(frame).unwrap()
+    }
+
+    fn step(visualizer: &mut Visualizer, steps: usize) {
+        for _ in 0..steps {
+            visualizer.step(&mut &b""[..], &mut io::sink()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_render_source() {
+        let source = "++++++++[>++++++++<-]>+.\n# done\n";
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let spans = source_map(&ast.data, &ast.spans);
+        let mut visualizer = Visualizer::new(source, Some(spans), Interpreter::new(ast.data));
+
+        step(&mut visualizer, 3);
+        let frame = render(&visualizer);
+        assert!(frame.contains("    1 | ++++++++[>++++++++<-]>+.\n      |           ^^^^^^^^\n"));
+        assert!(frame.contains("    2 | # done\n"));
+        assert!(frame.contains("step 3  @3 Incr(8)\n"));
+
+        step(&mut visualizer, 1000);
+        let frame = render(&visualizer);
+        assert!(frame.contains("output\n  A\n"));
+        assert!(frame.contains("value      0    65     0"));
+        assert!(frame.contains("\n                 ^\n"));
+        assert!(frame.ends_with("step 45  @10 <end>  halted\n"));
+    }
+
+    #[test]
+    fn test_render_optimized() {
+        let source = "+++[-]>,[>]";
+        let mut parser = Parser::new(Lexer::new(String::from(source)));
+        let ast = parser.parse_helper();
+        let mut optimizer = Optimizer::new(&ast.data);
+        optimizer.optimize_with(&OptimizerOptions::default());
+
+        let program = Interpreter::compile_ir(&optimizer.program);
+        let mut visualizer =
+            Visualizer::new(source, None, Interpreter::new_with_instructions(program));
+        let frame = render(&visualizer);
+        assert!(frame.contains("    1 | +++[-]>,[>]\n      | ^^^\n"));
+
+        // input is read from the given reader
+        for _ in 0..4 {
+            visualizer.step(&mut &b"x\n"[..], &mut io::sink()).unwrap();
+        }
+        assert_eq!(visualizer.interpreter().memory()[1], b'x');
+        assert!(render(&visualizer).contains("      |         ^^^\n"));
+    }
+
+    #[test]
+    fn test_listing_wraps() {
+        let interpreter =
+            Interpreter::new_with_instructions(vec![crate::interpreter::Instruction::Next(40); 3]);
+        let (lines, spans) = listing(&interpreter);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(spans[2].line, 3);
+        assert_eq!(spans[2].column, 1);
+    }
+}